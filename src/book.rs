@@ -7,10 +7,18 @@ use std::path::PathBuf;
 
 use unicode_normalization::UnicodeNormalization;
 
+use crate::metadata::Metadata;
+
 #[derive(Debug, Eq, Clone)]
 pub struct Book {
     name: String,
     path: PathBuf,
+    /// Metadata inferred from names of file and folders
+    metadata: Option<Metadata>,
+    /// Metadata embedded in file, read by scan with index cache
+    embedded: Option<Metadata>,
+    /// Content hash known from index cache
+    hash: Option<u64>,
 }
 
 impl Book {
@@ -19,9 +27,39 @@ impl Book {
         Book {
             name,
             path: PathBuf::from(path),
+            metadata: None,
+            embedded: None,
+            hash: None,
         }
     }
 
+    /// Book known by name other than its file name, like copy placed by
+    /// path template
+    pub fn with_name(name: String, path: PathBuf) -> Self {
+        Book {
+            name,
+            path,
+            metadata: None,
+            embedded: None,
+            hash: None,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn with_embedded(mut self, metadata: Metadata) -> Self {
+        self.embedded = Some(metadata);
+        self
+    }
+
+    pub fn with_hash(mut self, hash: u64) -> Self {
+        self.hash = Some(hash);
+        self
+    }
+
     pub fn get_name(&self) -> &String {
         &self.name
     }
@@ -30,6 +68,26 @@ impl Book {
         &self.path
     }
 
+    pub fn get_metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn get_hash(&self) -> Option<u64> {
+        self.hash
+    }
+
+    /// Metadata embedded in file, missing fields are filled by inferred ones
+    pub fn metadata(&self) -> Metadata {
+        let embedded = self
+            .embedded
+            .clone()
+            .unwrap_or_else(|| Metadata::read(&self.path));
+        match &self.metadata {
+            Some(inferred) => embedded.merge(inferred.clone()),
+            None => embedded,
+        }
+    }
+
     pub fn exists(&self) -> bool {
         self.path.exists()
     }
@@ -45,7 +103,13 @@ impl From<PathBuf> for Book {
             .unwrap()
             .nfc()
             .collect();
-        Book { name, path }
+        Book {
+            name,
+            path,
+            metadata: None,
+            embedded: None,
+            hash: None,
+        }
     }
 }
 
@@ -92,6 +156,9 @@ mod tests {
         let book = Book {
             name: "Test Book".to_string(),
             path: PathBuf::new(),
+            metadata: None,
+            embedded: None,
+            hash: None,
         };
         assert_eq!(format!("{}", book), String::from("Test Book"));
     }
@@ -111,14 +178,8 @@ mod tests {
 
     #[test]
     fn existing_checks() {
-        assert_eq!(
-            Book::from(PathBuf::from("tests/iterate/file_one.txt")).exists(),
-            true
-        );
+        assert!(Book::from(PathBuf::from("tests/iterate/file_one.txt")).exists());
 
-        assert_eq!(
-            Book::from(PathBuf::from("tests/iterate/file_four.txt")).exists(),
-            false
-        );
+        assert!(!Book::from(PathBuf::from("tests/iterate/file_four.txt")).exists());
     }
 }
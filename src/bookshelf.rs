@@ -3,13 +3,13 @@
 //! Collect books and other stuff
 
 use std::collections::BTreeSet;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::book::Book;
 
 type Books = BTreeSet<Book>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Bookshelf {
     books: Books,
     path: PathBuf,
@@ -31,10 +31,24 @@ impl Bookshelf {
         self.books.insert(book)
     }
 
+    pub fn remove(&mut self, book: &Book) -> bool {
+        self.books.remove(book)
+    }
+
     pub fn have(&self, book: &Book) -> bool {
         self.books.contains(book)
     }
 
+    pub fn get(&self, book: &Book) -> Option<&Book> {
+        self.books.get(book)
+    }
+
+    pub fn relative_path<'a>(&self, book: &'a Book) -> &'a Path {
+        book.get_path()
+            .strip_prefix(&self.path)
+            .unwrap_or_else(|_| book.get_path())
+    }
+
     pub fn difference(&self, other: &Self) -> Self {
         Bookshelf {
             books: self.books.difference(&other.books).cloned().collect(),
@@ -56,11 +70,47 @@ impl Bookshelf {
         }
     }
 
+    pub fn filter<P>(&self, predicate: P) -> Self
+    where
+        P: Fn(&Book) -> bool,
+    {
+        Bookshelf {
+            books: self
+                .books
+                .iter()
+                .filter(|b| predicate(b))
+                .cloned()
+                .collect(),
+            path: self.path.clone(),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Bookshelf {
+            books: self.books.union(&other.books).cloned().collect(),
+            path: self.path.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.books.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &Book> {
         self.books.iter()
     }
 }
 
+impl Default for Bookshelf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl From<PathBuf> for Bookshelf {
     fn from(path: PathBuf) -> Self {
         Bookshelf {
@@ -108,7 +158,21 @@ mod tests {
         let book = Book::new(String::from("Test book"));
         let mut bs = Bookshelf::new();
         bs.add(book.clone());
-        assert_eq!(bs.have(&book), true);
+        assert!(bs.have(&book));
+        assert!(bs.remove(&book));
+        assert!(!bs.have(&book));
+    }
+
+    #[test]
+    fn relative_book_path() {
+        let book = Book::from(PathBuf::from("some/test/path/inner/book.txt"));
+        let mut bs = Bookshelf::from(PathBuf::from("some/test/path"));
+        bs.add(book.clone());
+        assert_eq!(bs.get(&book), Some(&book));
+        assert_eq!(
+            bs.relative_path(&book),
+            PathBuf::from("inner/book.txt").as_path()
+        );
     }
 
     #[test]
@@ -131,5 +195,9 @@ mod tests {
 
         let inter2_to_1: Vec<_> = bs2.intersection(&bs1).iter().cloned().collect();
         assert_eq!(inter2_to_1, [Book::new(String::from("Test book2"))]);
+
+        assert_eq!(bs1.union(&bs2).len(), 3);
+        assert!(!bs1.is_empty());
+        assert!(Bookshelf::new().is_empty());
     }
 }
@@ -0,0 +1,255 @@
+//! Index cache entity
+//!
+//! Index of side stored between runs: directories with unchanged mtime are
+//! not read again, files with unchanged size and mtime keep their hash and
+//! metadata
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dirs::cache_dir;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::Metadata;
+use crate::utility::{create_dir_for_path, text_hash};
+
+/// Version of cache format, cache of other version is read from scratch
+pub const CACHE_VERSION: u32 = 3;
+
+/// Coarse file systems like FAT keep mtime unchanged by changes within this
+/// time, entries changed so recently are read again by next scan
+const MTIME_GRANULARITY: Duration = Duration::from_secs(2);
+
+/// Index caches of current user
+pub fn user_cache_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("index"))
+}
+
+/// Modification time in nanoseconds, 0 if unknown or too recent to trust
+pub(crate) fn mtime(metadata: &fs::Metadata) -> u64 {
+    let now = SystemTime::now();
+
+    metadata
+        .modified()
+        .ok()
+        .filter(|modified| {
+            now.duration_since(*modified)
+                .map(|age| age >= MTIME_GRANULARITY)
+                .unwrap_or(false)
+        })
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Book file, hash is known only if it was needed by some sync
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct FileRecord {
+    pub size: u64,
+    pub mtime: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<u64>,
+    /// Metadata embedded in file
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+/// Names of entries of directory: subdirectories, book files, links and
+/// special files like FIFOs
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub(crate) struct DirRecord {
+    pub mtime: u64,
+    pub dirs: Vec<String>,
+    pub files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub special: Vec<String>,
+}
+
+/// Index of root, paths are relative to root
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct IndexCache {
+    version: u32,
+    root: PathBuf,
+    dirs: BTreeMap<PathBuf, DirRecord>,
+    files: BTreeMap<PathBuf, FileRecord>,
+}
+
+impl IndexCache {
+    pub fn new(root: PathBuf) -> Self {
+        IndexCache {
+            version: CACHE_VERSION,
+            root: IndexCache::canonical(&root),
+            ..IndexCache::default()
+        }
+    }
+
+    /// Root known by absolute path, relative root of other working directory
+    /// gets other cache
+    fn canonical(root: &Path) -> PathBuf {
+        fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf())
+    }
+
+    fn path(dir: &Path, root: &Path) -> PathBuf {
+        let root = IndexCache::canonical(root);
+        dir.join(format!("{:016x}.json", text_hash(&root.to_string_lossy())))
+    }
+
+    /// Cache of root stored in dir, empty if missing or unreadable
+    pub fn load(dir: &Path, root: &Path) -> Self {
+        let path = IndexCache::path(dir, root);
+
+        let cache = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice::<IndexCache>(&content)
+                .map_err(|e| warn!("Index cache {} is dropped: {}", path.display(), e))
+                .ok(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Index cache {} is dropped: {}", path.display(), e);
+                None
+            }
+        };
+
+        match cache {
+            Some(cache)
+                if cache.version == CACHE_VERSION && cache.root == IndexCache::canonical(root) =>
+            {
+                debug!("Index cache of {} is loaded", root.display());
+                cache
+            }
+            _ => IndexCache::new(root.to_path_buf()),
+        }
+    }
+
+    pub fn store(&self, dir: &Path) -> Result<(), String> {
+        let path = IndexCache::path(dir, &self.root);
+        // Written aside and renamed, broken write keeps old cache
+        let temporary = path.with_extension("json.tmp");
+
+        let content = serde_json::to_vec(self)
+            .map_err(|e| format!("fail to serialize index cache with error: {}", e))?;
+        create_dir_for_path(&path)
+            .and_then(|_| fs::write(&temporary, content))
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|e| {
+                format!(
+                    "fail to store index cache: {} with error: {}",
+                    path.display(),
+                    e
+                )
+            })
+    }
+
+    /// Store hashes computed for files of root, files changed since their
+    /// scan are skipped
+    pub fn remember(
+        dir: &Path,
+        root: &Path,
+        hashes: &BTreeMap<PathBuf, u64>,
+    ) -> Result<(), String> {
+        let mut cache = IndexCache::load(dir, root);
+
+        let mut changed = false;
+        for (path, hash) in hashes {
+            let relative = match path.strip_prefix(root) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            let metadata = match fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            if let Some(record) = cache.file(relative, metadata.len(), mtime(&metadata)) {
+                if record.hash != Some(*hash) {
+                    cache.files.get_mut(relative).unwrap().hash = Some(*hash);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            cache.store(dir)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Directory which isn't changed since its scan
+    pub(crate) fn dir(&self, relative: &Path, mtime: u64) -> Option<&DirRecord> {
+        self.dirs
+            .get(relative)
+            .filter(|record| mtime != 0 && record.mtime == mtime)
+    }
+
+    /// File which isn't changed since its scan
+    pub(crate) fn file(&self, relative: &Path, size: u64, mtime: u64) -> Option<&FileRecord> {
+        self.files
+            .get(relative)
+            .filter(|record| mtime != 0 && record.mtime == mtime && record.size == size)
+    }
+
+    pub(crate) fn add_dir(&mut self, relative: PathBuf, record: DirRecord) {
+        self.dirs.insert(relative, record);
+    }
+
+    pub(crate) fn add_file(&mut self, relative: PathBuf, record: FileRecord) {
+        self.files.insert(relative, record);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_remember() {
+        let dir = PathBuf::from("tests/cache/index");
+        let root = PathBuf::from("tests/iterate");
+        let book = root.join("file_one.txt");
+        let metadata = fs::metadata(&book).unwrap();
+
+        let mut cache = IndexCache::new(root.clone());
+        cache.add_file(
+            PathBuf::from("file_one.txt"),
+            FileRecord {
+                size: metadata.len(),
+                mtime: mtime(&metadata),
+                hash: None,
+                metadata: Metadata::default(),
+            },
+        );
+        let stored = cache.store(&dir);
+
+        let mut hashes = BTreeMap::new();
+        hashes.insert(book, 42);
+        let remembered = IndexCache::remember(&dir, &root, &hashes);
+
+        let loaded = IndexCache::load(&dir, &root);
+        let other = IndexCache::load(&dir, Path::new("tests/other"));
+
+        if let Err(e) = fs::remove_dir_all("tests/cache") {
+            panic!("Remove error: {}", e);
+        }
+
+        assert_eq!(stored, Ok(()));
+        assert_eq!(remembered, Ok(()));
+        // Fixtures are older than mtime granularity
+        assert_eq!(
+            loaded
+                .file(Path::new("file_one.txt"), metadata.len(), mtime(&metadata))
+                .and_then(|r| r.hash),
+            Some(42)
+        );
+        assert!(other.is_empty());
+    }
+}
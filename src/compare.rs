@@ -0,0 +1,164 @@
+//! Compare entity
+//!
+//! Book by book comparison of two sides
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::book::Book;
+use crate::bookshelf::Bookshelf;
+use crate::utility::file_hash;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// Equal path and size, or content for content comparison
+    Same,
+    /// Different paths on sides
+    Moved,
+    /// Different size, or content for content comparison
+    Changed,
+    OnlyLocal,
+    OnlyForeign,
+    /// File of any side can't be read
+    Unreadable(String),
+}
+
+impl Difference {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difference::Same => "same",
+            Difference::Moved => "moved",
+            Difference::Changed => "changed",
+            Difference::OnlyLocal => "only_source",
+            Difference::OnlyForeign => "only_destination",
+            Difference::Unreadable(_) => "unreadable",
+        }
+    }
+}
+
+/// Book of one or both sides, paths are relative to side roots
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    name: String,
+    local: Option<PathBuf>,
+    foreign: Option<PathBuf>,
+    difference: Difference,
+}
+
+impl Comparison {
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_local(&self) -> Option<&PathBuf> {
+        self.local.as_ref()
+    }
+
+    pub fn get_foreign(&self) -> Option<&PathBuf> {
+        self.foreign.as_ref()
+    }
+
+    pub fn get_difference(&self) -> &Difference {
+        &self.difference
+    }
+
+    /// Paths differ on purpose, like copy placed by template or inbox
+    pub(crate) fn placed(mut self) -> Self {
+        if self.difference == Difference::Moved {
+            self.difference = Difference::Same;
+        }
+        self
+    }
+}
+
+fn same_content(local: &Path, foreign: &Path, content: bool) -> Result<bool, String> {
+    let size = |path: &Path| {
+        fs::metadata(path)
+            .map(|m| m.len())
+            .map_err(|e| format!("fail to read: {} with error: {}", path.display(), e))
+    };
+    if size(local)? != size(foreign)? {
+        return Ok(false);
+    }
+    if !content {
+        return Ok(true);
+    }
+
+    let hash = |path: &Path| {
+        file_hash(path).map_err(|e| format!("fail to read: {} with error: {}", path.display(), e))
+    };
+    Ok(hash(local)? == hash(foreign)?)
+}
+
+fn compare_book(local: &Bookshelf, foreign: &Bookshelf, book: &Book, content: bool) -> Comparison {
+    let local_book = local.get(book);
+    let foreign_book = foreign.get(book);
+
+    let difference = match (local_book, foreign_book) {
+        (Some(_), None) => Difference::OnlyLocal,
+        (None, _) => Difference::OnlyForeign,
+        (Some(l), Some(f)) => match same_content(l.get_path(), f.get_path(), content) {
+            Err(e) => Difference::Unreadable(e),
+            Ok(false) => Difference::Changed,
+            Ok(true) if local.relative_path(l) != foreign.relative_path(f) => Difference::Moved,
+            Ok(true) => Difference::Same,
+        },
+    };
+
+    Comparison {
+        name: book.get_name().to_string(),
+        local: local_book.map(|b| local.relative_path(b).to_path_buf()),
+        foreign: foreign_book.map(|b| foreign.relative_path(b).to_path_buf()),
+        difference,
+    }
+}
+
+/// Compare every book of both sides, sizes are compared always and content
+/// hashes only if `content` is set
+pub fn compare(local: &Bookshelf, foreign: &Bookshelf, content: bool) -> Vec<Comparison> {
+    local
+        .union(foreign)
+        .iter()
+        .map(|book| compare_book(local, foreign, book, content))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::Indexer;
+
+    fn differences(content: bool) -> Vec<(String, Difference)> {
+        compare(
+            &Indexer::new(PathBuf::from("tests/compare/local")).index(),
+            &Indexer::new(PathBuf::from("tests/compare/foreign")).index(),
+            content,
+        )
+        .into_iter()
+        .map(|c| (c.get_name().to_string(), c.get_difference().clone()))
+        .collect()
+    }
+
+    #[test]
+    fn compare_sizes() {
+        assert_eq!(
+            differences(false),
+            [
+                (String::from("changed.txt"), Difference::Changed),
+                (String::from("changed_content.txt"), Difference::Same),
+                (String::from("foreign_only.txt"), Difference::OnlyForeign),
+                (String::from("local_only.txt"), Difference::OnlyLocal),
+                (String::from("moved.txt"), Difference::Moved),
+                (String::from("same.txt"), Difference::Same),
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_content() {
+        assert_eq!(
+            differences(true)[1],
+            (String::from("changed_content.txt"), Difference::Changed)
+        );
+    }
+}
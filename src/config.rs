@@ -1,29 +1,809 @@
 //! Config entity
 //!
-//! Main config storage - store synchronization paths
+//! Main config storage - store synchronization profiles
 
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use log::warn;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::device::{Device, DEVICES};
+use crate::direction::{Direction, Directions};
+use crate::filter::{patterns, Filter, Rule};
+use crate::guard::{Guard, DEFAULT_MAX_CHANGE_PERCENT};
+use crate::inbox::{Filing, Inbox};
+use crate::indexer::{Links, WalkOptions};
+use crate::naming::{Naming, NamingRule};
+use crate::template::Template;
+use crate::trash::DEFAULT_RETENTION_DAYS;
+use crate::updater::DEFAULT_CONCURRENCY;
+
+/// Name of profile for config with single source/destination pair
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Version of config layout written by this build
+///
+/// 1 - single source/destination pair (files without version), 2 - profiles
+pub const CONFIG_VERSION: u64 = 2;
+
+const STORAGE_KEYS: &[&str] = &["version", "profiles"];
+const PROFILE_KEYS: &[&str] = &[
+    "source",
+    "destination",
+    "destinations",
+    "direction",
+    "rules",
+    "naming",
+    "delete",
+    "inbox",
+    "inbox_filing",
+    "trash_retention",
+    "max_change",
+    "concurrency",
+    "index_cache",
+    "require_complete_index",
+    "source_links",
+    "destination_links",
+    "hidden",
+    "max_depth",
+    "devices",
+];
+const DESTINATION_KEYS: &[&str] = &["path", "template"];
+const SELECTION_KEYS: &[&str] = &[
+    "include",
+    "exclude",
+    "patterns",
+    "exclude_patterns",
+    "languages",
+    "formats",
+    "authors",
+];
+const RULE_KEYS: &[&str] = &["path", "direction"];
+const NAMING_KEYS: &[&str] = &["pattern", "scope"];
+
+/// Config file format, chosen by file extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// Format of file, YAML for unknown extension
+    pub fn of(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .as_deref()
+        {
+            Some("toml") => Format::Toml,
+            Some("json") => Format::Json,
+            _ => Format::Yaml,
+        }
+    }
+
+    fn parse(&self, s: &str) -> Result<Value, String> {
+        let value = match self {
+            Format::Yaml => serde_yaml::from_str(s).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str(s).map_err(|e| e.to_string()),
+            Format::Json => serde_json::from_str(s).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| format!("fail to parse config with error: {}", e))?;
+
+        // Empty YAML file is null
+        Ok(match value {
+            Value::Null => Value::Object(Map::new()),
+            value => value,
+        })
+    }
+
+    fn serialize<T: Serialize>(&self, storage: &T) -> Result<String, String> {
+        match self {
+            Format::Yaml => serde_yaml::to_string(storage).map_err(|e| e.to_string()),
+            // Value puts plain values before tables as TOML requires
+            Format::Toml => toml::Value::try_from(storage)
+                .and_then(|v| toml::to_string(&v))
+                .map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_string_pretty(storage).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| format!("fail to serialize config with error: {}", e))
+    }
+}
 
 pub struct Config {
     path: PathBuf,
+    strict: bool,
+}
+
+fn default_trash_retention() -> u64 {
+    DEFAULT_RETENTION_DAYS
+}
+
+fn default_max_change() -> u8 {
+    DEFAULT_MAX_CHANGE_PERCENT
+}
+
+fn default_concurrency() -> usize {
+    DEFAULT_CONCURRENCY
+}
+
+fn default_index_cache() -> bool {
+    true
+}
+
+fn default_devices() -> Vec<Device> {
+    DEVICES.to_vec()
+}
+
+fn is_default_concurrency(concurrency: &usize) -> bool {
+    *concurrency == DEFAULT_CONCURRENCY
+}
+
+fn is_default_index_cache(index_cache: &bool) -> bool {
+    *index_cache == default_index_cache()
+}
+
+fn is_default_devices(devices: &[Device]) -> bool {
+    devices == DEVICES
+}
+
+pub(crate) fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    value == &T::default()
+}
+
+/// Rules selecting part of library, all rules should allow book
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Selection {
+    /// Folders of library to synchronize, all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    /// Folders of library to skip
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<PathBuf>,
+    /// Glob patterns of books to synchronize, all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+    /// Glob patterns of books to skip
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_patterns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub languages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub formats: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+}
+
+impl Selection {
+    pub fn filter(&self) -> Result<Filter, String> {
+        let mut filter = Filter::new(self.include.clone(), self.exclude.clone());
+
+        if !self.patterns.is_empty() {
+            filter = filter.with(Rule::Patterns(patterns(&self.patterns)?));
+        }
+        if !self.exclude_patterns.is_empty() {
+            filter = filter.with(Rule::ExcludePatterns(patterns(&self.exclude_patterns)?));
+        }
+        if !self.languages.is_empty() {
+            filter = filter.with(Rule::Languages(self.languages.clone()));
+        }
+        if !self.formats.is_empty() {
+            filter = filter.with(Rule::Formats(self.formats.clone()));
+        }
+        if !self.authors.is_empty() {
+            filter = filter.with(Rule::Authors(self.authors.clone()));
+        }
+
+        Ok(filter)
+    }
+}
+
+/// Direction of subtrees of library matched by glob pattern
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DirectionRule {
+    /// Glob pattern of folder or book path relative to library root
+    pub path: String,
+    pub direction: Direction,
+}
+
+/// Destination of synchronization, e-ink device or backup folder
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Destination {
+    pub path: PathBuf,
+    /// Layout of destination by book metadata, mirrors source layout if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Part of library received by destination, narrows profile selection
+    #[serde(flatten)]
+    pub selection: Selection,
+}
+
+impl Destination {
+    pub fn new(path: PathBuf) -> Self {
+        Destination {
+            path,
+            template: None,
+            selection: Selection::default(),
+        }
+    }
+
+    pub fn template(&self) -> Result<Option<Template>, String> {
+        self.template.as_deref().map(Template::parse).transpose()
+    }
+}
+
+/// Synchronization of source with one or several destinations
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub source: PathBuf,
+    /// Single destination, shortcut for one item of destinations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<Destination>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub direction: Direction,
+    /// Directions of subtrees overriding profile direction, first matched
+    /// rule wins
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<DirectionRule>,
+    /// Rules inferring metadata of books from file and folder names, first
+    /// matched rule wins
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub naming: Vec<NamingRule>,
+    /// Part of library synchronized with all destinations
+    #[serde(flatten)]
+    pub selection: Selection,
+    /// Propagate deletions through trash
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub delete: bool,
+    /// Folder of source receiving books found only on destination, relative
+    /// to source root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbox: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub inbox_filing: Filing,
+    #[serde(default = "default_trash_retention")]
+    pub trash_retention: u64,
+    #[serde(default = "default_max_change")]
+    pub max_change: u8,
+    /// Transfers running at once, slow flash devices degrade under parallel
+    /// writes
+    #[serde(
+        default = "default_concurrency",
+        skip_serializing_if = "is_default_concurrency"
+    )]
+    pub concurrency: usize,
+    /// Keep indexes of sides between runs, only changed folders are read
+    #[serde(
+        default = "default_index_cache",
+        skip_serializing_if = "is_default_index_cache"
+    )]
+    pub index_cache: bool,
+    /// Refuse sync if some entries of sides can't be indexed
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub require_complete_index: bool,
+    /// Symbolic links of source: ignore, follow or copy
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub source_links: Links,
+    /// Symbolic links of destinations: ignore, follow or copy
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub destination_links: Links,
+    /// Index hidden files and folders
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub hidden: bool,
+    /// Depth of folders walked below roots of sides, 0 is root only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// Device types whose system folders are skipped, all known by default
+    #[serde(
+        default = "default_devices",
+        skip_serializing_if = "is_default_devices"
+    )]
+    pub devices: Vec<Device>,
+}
+
+impl Profile {
+    pub fn new(source: PathBuf, destination: PathBuf) -> Self {
+        Profile {
+            source,
+            destination: Some(destination),
+            destinations: Vec::new(),
+            direction: Direction::default(),
+            rules: Vec::new(),
+            naming: Vec::new(),
+            selection: Selection::default(),
+            delete: false,
+            inbox: None,
+            inbox_filing: Filing::default(),
+            trash_retention: DEFAULT_RETENTION_DAYS,
+            max_change: DEFAULT_MAX_CHANGE_PERCENT,
+            concurrency: DEFAULT_CONCURRENCY,
+            index_cache: true,
+            require_complete_index: false,
+            source_links: Links::default(),
+            destination_links: Links::default(),
+            hidden: false,
+            max_depth: None,
+            devices: default_devices(),
+        }
+    }
+
+    /// Options of walk of both sides
+    pub fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            hidden: self.hidden,
+            max_depth: self.max_depth,
+            devices: self.devices.clone(),
+        }
+    }
+
+    /// All destinations of profile, single destination first
+    pub fn destinations(&self) -> Vec<Destination> {
+        self.destination
+            .iter()
+            .cloned()
+            .map(Destination::new)
+            .chain(self.destinations.iter().cloned())
+            .collect()
+    }
+
+    /// Profile direction with directions of subtrees
+    pub fn directions(&self) -> Result<Directions, String> {
+        self.rules
+            .iter()
+            .try_fold(Directions::new(self.direction), |directions, rule| {
+                let pattern = patterns(std::slice::from_ref(&rule.path))?.remove(0);
+                Ok(directions.with_rule(pattern, rule.direction))
+            })
+    }
+
+    pub fn naming(&self) -> Result<Naming, String> {
+        Naming::new(&self.naming)
+    }
+
+    pub fn inbox(&self) -> Option<Inbox> {
+        self.inbox
+            .as_ref()
+            .map(|path| Inbox::new(path.clone(), self.inbox_filing))
+    }
+
+    /// Missing paths and inconsistent options of profile
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if !self.source.exists() {
+            problems.push(format!(
+                "source: {} doesn't exist",
+                self.source.to_str().unwrap()
+            ));
+        }
+
+        let destinations = self.destinations();
+        if destinations.is_empty() {
+            problems.push(String::from("no destination"));
+        }
+
+        for destination in destinations.iter() {
+            if !destination.path.exists() {
+                problems.push(format!(
+                    "destination: {} doesn't exist",
+                    destination.path.to_str().unwrap()
+                ));
+            } else if self.source.exists() {
+                if let Err(e) = Guard::default().check_paths(&self.source, &destination.path) {
+                    problems.push(e);
+                }
+            }
+
+            if let Err(e) = self.filter(destination) {
+                problems.push(e);
+            }
+            if let Err(e) = destination.template() {
+                problems.push(e);
+            }
+        }
+
+        if let Err(e) = self.directions() {
+            problems.push(e);
+        }
+        if let Err(e) = self.naming() {
+            problems.push(e);
+        }
+        if self.max_change > 100 {
+            problems.push(format!(
+                "max_change: {} is more than 100 percent",
+                self.max_change
+            ));
+        }
+        if self.concurrency == 0 {
+            problems.push(String::from("concurrency: 0 should be at least 1"));
+        }
+        if let Some(inbox) = &self.inbox {
+            if inbox.is_absolute() {
+                problems.push(format!(
+                    "inbox: {} should be relative to source",
+                    inbox.to_str().unwrap()
+                ));
+            }
+            if self.direction == Direction::Push || self.direction == Direction::Mirror {
+                problems.push(String::from(
+                    "inbox is never filled by push or mirror direction",
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// Filter of books synchronized with destination
+    pub fn filter(&self, destination: &Destination) -> Result<Filter, String> {
+        Ok(self
+            .selection
+            .filter()?
+            .and(destination.selection.filter()?))
+    }
 }
 
+/// Config file layout of current version
 #[derive(Serialize, Deserialize)]
 pub struct ConfigStorage {
-    source: PathBuf,
-    destination: PathBuf,
+    version: u64,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Default for ConfigStorage {
+    fn default() -> Self {
+        ConfigStorage {
+            version: CONFIG_VERSION,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+/// Version of config, config without version is of first version
+fn version_of(value: &Value) -> Result<u64, String> {
+    match value.get("version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("config version: {} should be number", version)),
+        None => Ok(1),
+    }
+}
+
+/// Single source/destination pair is moved to default profile
+fn migrate_v1(config: &mut Map<String, Value>) -> Result<(), String> {
+    match (config.remove("source"), config.remove("destination")) {
+        (Some(source), Some(destination)) => {
+            let profiles = config
+                .entry("profiles")
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .ok_or_else(|| String::from("profiles should be map"))?;
+
+            if profiles.contains_key(DEFAULT_PROFILE) {
+                return Err(format!(
+                    "profile: {} is defined twice, by paths and in profiles",
+                    DEFAULT_PROFILE
+                ));
+            }
+
+            let mut profile = Map::new();
+            profile.insert(String::from("source"), source);
+            profile.insert(String::from("destination"), destination);
+            profiles.insert(DEFAULT_PROFILE.to_string(), Value::Object(profile));
+            Ok(())
+        }
+        (None, None) => Ok(()),
+        (_, _) => Err(String::from("both source and destination should be set")),
+    }
+}
+
+/// Upgrade config of any older version to layout of current version
+fn migrate(mut value: Value) -> Result<Value, String> {
+    let version = version_of(&value)?;
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "config version: {} is newer than supported: {}",
+            version, CONFIG_VERSION
+        ));
+    }
+
+    let config = value
+        .as_object_mut()
+        .ok_or_else(|| String::from("config should be map"))?;
+
+    if version < 2 {
+        migrate_v1(config)?;
+    }
+    config.insert(String::from("version"), Value::from(CONFIG_VERSION));
+
+    Ok(value)
+}
+
+/// Path of key in config, array items are `[index]` segments
+type KeyPath = Vec<String>;
+
+fn unknown_in(value: &Value, allowed: &[&[&str]], path: &[String], unknown: &mut Vec<KeyPath>) {
+    if let Some(map) = value.as_object() {
+        for key in map.keys() {
+            if !allowed.iter().any(|keys| keys.contains(&key.as_str())) {
+                let mut key_path = path.to_vec();
+                key_path.push(key.clone());
+                unknown.push(key_path);
+            }
+        }
+    }
+}
+
+fn items_of<'a>(value: &'a Value, key: &str) -> impl Iterator<Item = (usize, &'a Value)> {
+    value
+        .get(key)
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .enumerate()
+}
+
+/// Keys of config not used by any setting
+fn unknown_keys(value: &Value) -> Vec<KeyPath> {
+    let mut unknown = Vec::new();
+    unknown_in(value, &[STORAGE_KEYS], &[], &mut unknown);
+
+    let profiles = value.get("profiles").and_then(|p| p.as_object());
+    for (name, profile) in profiles.into_iter().flatten() {
+        let path = vec![String::from("profiles"), name.clone()];
+        unknown_profile_keys(profile, &path, &mut unknown);
+    }
+
+    // NOTE: keys keep order of file, they are reported sorted
+    unknown.sort();
+    unknown
+}
+
+fn unknown_profile_keys(profile: &Value, path: &[String], unknown: &mut Vec<KeyPath>) {
+    unknown_in(profile, &[PROFILE_KEYS, SELECTION_KEYS], path, unknown);
+
+    for (key, keys) in [
+        ("destinations", DESTINATION_KEYS),
+        ("rules", RULE_KEYS),
+        ("naming", NAMING_KEYS),
+    ]
+    .iter()
+    {
+        for (index, item) in items_of(profile, key) {
+            let mut path = path.to_vec();
+            path.push(key.to_string());
+            path.push(format!("[{}]", index));
+
+            let allowed: &[&[&str]] = if *key == "destinations" {
+                &[keys, SELECTION_KEYS]
+            } else {
+                &[keys]
+            };
+            unknown_in(item, allowed, &path, unknown);
+        }
+    }
+}
+
+/// Line declaring key as YAML/TOML/JSON key or as part of TOML table header
+fn is_key_line(line: &str, key: &str) -> bool {
+    let line = line.trim_start();
+    let line = line.strip_prefix("- ").unwrap_or(line).trim_start();
+
+    if line.starts_with('[') {
+        return line
+            .trim_matches(|c| c == '[' || c == ']')
+            .split('.')
+            .any(|part| part.trim().trim_matches('"') == key);
+    }
+
+    let (line, quote) = match line.chars().next() {
+        Some(quote) if quote == '"' || quote == '\'' => (&line[1..], Some(quote)),
+        _ => (line, None),
+    };
+
+    match line.strip_prefix(key) {
+        Some(rest) => {
+            let rest = match quote {
+                Some(quote) => match rest.strip_prefix(quote) {
+                    Some(rest) => rest,
+                    None => return false,
+                },
+                None => rest,
+            };
+            let rest = rest.trim_start();
+            rest.starts_with(':') || rest.starts_with('=')
+        }
+        None => false,
+    }
+}
+
+/// Line number (from 1) of key path in config text, segments are searched one
+/// after another
+fn line_of(text: &str, path: &[String]) -> Option<usize> {
+    let lines = text.lines().collect::<Vec<&str>>();
+    let mut line = 0;
+
+    for segment in path.iter().filter(|s| !s.starts_with('[')) {
+        line += lines[line..].iter().position(|l| is_key_line(l, segment))?;
+    }
+
+    Some(line + 1)
+}
+
+/// Describe key paths with line numbers of config text
+fn describe(text: &str, paths: &[KeyPath]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| match line_of(text, path) {
+            Some(line) => format!("{} at line {}", key_name(path), line),
+            None => key_name(path),
+        })
+        .collect()
+}
+
+fn key_name(path: &[String]) -> String {
+    path.iter().fold(String::new(), |name, segment| {
+        if name.is_empty() || segment.starts_with('[') {
+            name + segment
+        } else {
+            name + "." + segment
+        }
+    })
+}
+
+/// Values of config by dotted keys, maps are walked, arrays are values
+pub fn leaves(value: &Value) -> Vec<(String, Value)> {
+    fn walk(value: &Value, path: &str, leaves: &mut Vec<(String, Value)>) {
+        match value {
+            Value::Object(map) if !map.is_empty() || path.is_empty() => {
+                for (key, value) in map {
+                    let path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    walk(value, &path, leaves);
+                }
+            }
+            value => leaves.push((path.to_string(), value.clone())),
+        }
+    }
+
+    let mut leaves = Vec::new();
+    walk(value, "", &mut leaves);
+    leaves
+}
+
+/// Value of dotted key, number segments are indexes of arrays
+pub fn value_at<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.')
+        .try_fold(value, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+/// Set value of dotted key, missing maps are created, index equal to array
+/// length appends item
+fn set_at(value: &mut Value, key: &str, new: Value) -> Result<(), String> {
+    let (parent, last) = match key.rfind('.') {
+        Some(dot) => (Some(&key[..dot]), &key[dot + 1..]),
+        None => (None, key),
+    };
+
+    let mut target = value;
+    for segment in parent.into_iter().flat_map(|p| p.split('.')) {
+        target = match target {
+            Value::Object(map) => map
+                .entry(segment)
+                .or_insert_with(|| Value::Object(Map::new())),
+            Value::Array(items) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(move |i| items.get_mut(i))
+                .ok_or_else(|| format!("key: {} has no item {}", key, segment))?,
+            _ => return Err(format!("key: {} is inside of value", key)),
+        };
+    }
+
+    match target {
+        Value::Object(map) => {
+            map.insert(last.to_string(), new);
+            Ok(())
+        }
+        Value::Array(items) => match last.parse::<usize>() {
+            Ok(i) if i < items.len() => {
+                items[i] = new;
+                Ok(())
+            }
+            Ok(i) if i == items.len() => {
+                items.push(new);
+                Ok(())
+            }
+            _ => Err(format!("key: {} has no item {}", key, last)),
+        },
+        _ => Err(format!("key: {} is inside of value", key)),
+    }
+}
+
+/// Remove value of dotted key, returns removed value
+fn unset_at(value: &mut Value, key: &str) -> Option<Value> {
+    let (parent, last) = match key.rfind('.') {
+        Some(dot) => (Some(&key[..dot]), &key[dot + 1..]),
+        None => (None, key),
+    };
+
+    let mut target = value;
+    for segment in parent.into_iter().flat_map(|p| p.split('.')) {
+        target = match target {
+            Value::Object(map) => map.get_mut(segment)?,
+            Value::Array(items) => items.get_mut(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    match target {
+        Value::Object(map) => map.remove(last),
+        Value::Array(items) => match last.parse::<usize>() {
+            Ok(i) if i < items.len() => Some(items.remove(i)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Value of command line text, YAML scalars and flow collections are parsed,
+/// other text is string
+pub fn parse_value(text: &str) -> Value {
+    serde_yaml::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+/// Profiles of config value of any version
+pub fn profiles_of(value: Value) -> Result<BTreeMap<String, Profile>, String> {
+    let storage: ConfigStorage = serde_json::from_value(migrate(value)?)
+        .map_err(|e| format!("fail to parse config with error: {}", e))?;
+
+    Ok(storage.profiles)
+}
+
+/// Config value of current version with profiles
+pub fn value_of(profiles: BTreeMap<String, Profile>) -> Result<Value, String> {
+    serde_json::to_value(ConfigStorage {
+        profiles,
+        ..ConfigStorage::default()
+    })
+    .map_err(|e| format!("fail to serialize config with error: {}", e))
 }
 
 impl Config {
     pub fn new(path: PathBuf) -> Self {
-        Config { path }
+        Config {
+            path,
+            strict: false,
+        }
     }
 
-    pub fn parse(&self) -> Result<(PathBuf, PathBuf), String> {
+    /// Refuse config with unknown keys instead of ignoring them
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn read(&self) -> Result<(String, Value), String> {
         if !self.path.exists() {
             return Err(format!(
                 "Script: {} doesn't exist",
@@ -32,45 +812,240 @@ impl Config {
         }
 
         let mut f = File::open(self.path.clone())
-            .or_else(|e| Err(format!("fail to open config with error: {}", e)))?;
+            .map_err(|e| format!("fail to open config with error: {}", e))?;
 
         let mut s = String::new();
         f.read_to_string(&mut s)
-            .or_else(|e| Err(format!("fail to read config with error: {}", e)))?;
+            .map_err(|e| format!("fail to read config with error: {}", e))?;
 
-        let ConfigStorage {
-            source,
-            destination,
-        } = serde_yaml::from_str(&s)
-            .or_else(|e| Err(format!("fail to parse config with error: {}", e)))?;
+        let value = Format::of(&self.path).parse(&s)?;
 
-        Ok((source, destination))
+        Ok((s, value))
     }
 
-    pub fn store(&self, source: PathBuf, destination: PathBuf) -> Result<(), String> {
+    /// Version of config file layout
+    pub fn version(&self) -> Result<u64, String> {
+        version_of(&self.read()?.1)
+    }
+
+    /// Unknown keys of config with line numbers
+    pub fn unknown_keys(&self) -> Result<Vec<String>, String> {
+        let (s, value) = self.read()?;
+
+        Ok(describe(&s, &unknown_keys(&migrate(value)?)))
+    }
+
+    fn check_unknown(&self, unknown: Vec<String>) -> Result<(), String> {
+        if self.strict && !unknown.is_empty() {
+            return Err(format!("unknown keys: {}", unknown.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// Config of current version as generic value, layer of layered config
+    pub fn layer(&self) -> Result<Value, String> {
+        let (s, value) = self.read()?;
+        let value = migrate(value)?;
+
+        self.check_unknown(describe(&s, &unknown_keys(&value)))?;
+
+        Ok(value)
+    }
+
+    /// Settings of single profile as generic value, file has keys of profile
+    /// on top level (per-library config)
+    pub fn profile_layer(&self) -> Result<Value, String> {
+        let (s, value) = self.read()?;
+        if !value.is_object() {
+            return Err(String::from("config should be map"));
+        }
+
+        let mut unknown = Vec::new();
+        unknown_profile_keys(&value, &[], &mut unknown);
+        self.check_unknown(describe(&s, &unknown))?;
+
+        Ok(value)
+    }
+
+    fn load(&self) -> Result<ConfigStorage, String> {
+        serde_json::from_value(self.layer()?)
+            .map_err(|e| format!("fail to parse config with error: {}", e))
+    }
+
+    fn write<T: Serialize>(&self, storage: &T) -> Result<(), String> {
+        let serialized = Format::of(&self.path).serialize(storage)?;
+
         let mut f = File::create(self.path.clone())
-            .or_else(|e| Err(format!("fail to create config with error: {}", e)))?;
+            .map_err(|e| format!("fail to create config with error: {}", e))?;
 
-        let config = ConfigStorage {
-            source,
-            destination,
-        };
+        f.write_all(serialized.as_bytes())
+            .map_err(|e| format!("fail to store config with error: {}", e))
+    }
+
+    /// Rewrite config of older version in current layout, old file is kept as
+    /// backup with version suffix
+    ///
+    /// Returns path of backup, nothing is done for config of current version
+    pub fn migrate(&self) -> Result<Option<PathBuf>, String> {
+        let version = self.version()?;
+        if version >= CONFIG_VERSION {
+            return Ok(None);
+        }
+
+        let storage = self.load()?;
+        let backup = self.backup(version)?;
+
+        self.write(&storage)?;
+
+        Ok(Some(backup))
+    }
+
+    /// Copy config of version beside it with version suffix
+    fn backup(&self, version: u64) -> Result<PathBuf, String> {
+        let mut backup = self.path.clone().into_os_string();
+        backup.push(format!(".v{}.bak", version));
+        let backup = PathBuf::from(backup);
+        fs::copy(&self.path, &backup)
+            .map_err(|e| format!("fail to backup config with error: {}", e))?;
+
+        Ok(backup)
+    }
+
+    /// Config value to edit, empty config for missing file
+    fn editable(&self) -> Result<Value, String> {
+        if self.path.exists() {
+            Ok(migrate(self.read()?.1)?)
+        } else {
+            serde_json::to_value(ConfigStorage::default())
+                .map_err(|e| format!("fail to serialize config with error: {}", e))
+        }
+    }
+
+    /// Store edited value if it's still valid config without new unknown
+    /// keys, other keys are kept in their order, comments are lost
+    ///
+    /// Config of older version is stored in current layout, old file is kept
+    /// as backup like by `migrate`
+    fn store_edited(&self, value: Value, key: &str) -> Result<(), String> {
+        profiles_of(value.clone())
+            .map_err(|e| format!("fail to change key: {} with error: {}", key, e))?;
+
+        if let Some(unknown) = unknown_keys(&value)
+            .iter()
+            .map(|path| key_name(path))
+            .find(|name| name.starts_with(key) || key.starts_with(name.as_str()))
+        {
+            return Err(format!("unknown key: {}", unknown));
+        }
+
+        if self.path.exists() {
+            let version = self.version()?;
+            if version < CONFIG_VERSION {
+                let backup = self.backup(version)?;
+                warn!(
+                    "Config of version {} is rewritten to version {}, old config is kept: {}",
+                    version,
+                    CONFIG_VERSION,
+                    backup.display()
+                );
+            }
+        }
+
+        self.write(&value)
+    }
+
+    /// Value of dotted key, e.g. `profiles.kobo.direction`
+    pub fn get(&self, key: &str) -> Result<Option<Value>, String> {
+        Ok(value_at(&self.editable()?, key).cloned())
+    }
+
+    /// All values of config by dotted keys
+    pub fn list(&self) -> Result<Vec<(String, Value)>, String> {
+        Ok(leaves(&self.editable()?))
+    }
+
+    /// Set value of dotted key, whole profile can be set by its key
+    pub fn set(&self, key: &str, new: Value) -> Result<(), String> {
+        let mut value = self.editable()?;
+        set_at(&mut value, key, new)?;
+        self.store_edited(value, key)
+    }
+
+    /// Remove dotted key, returns false if key isn't set
+    pub fn unset(&self, key: &str) -> Result<bool, String> {
+        let mut value = self.editable()?;
+        if unset_at(&mut value, key).is_none() {
+            return Ok(false);
+        }
+        self.store_edited(value, key)?;
+        Ok(true)
+    }
+
+    /// Problems of config: unknown keys, missing paths and inconsistent
+    /// options, error is returned for config which can't be parsed
+    pub fn validate(&self) -> Result<Vec<String>, String> {
+        let mut problems = self
+            .unknown_keys()?
+            .into_iter()
+            .map(|key| format!("unknown key: {}", key))
+            .collect::<Vec<String>>();
+
+        for (name, profile) in self.profiles()? {
+            problems.extend(
+                profile
+                    .problems()
+                    .into_iter()
+                    .map(|p| format!("profile: {}: {}", name, p)),
+            );
+        }
+
+        Ok(problems)
+    }
 
-        let serialized = serde_yaml::to_string(&config)
-            .or_else(|e| Err(format!("fail to serialize config with error: {}", e)))?;
+    /// Source/destination paths of default profile
+    pub fn parse(&self) -> Result<(PathBuf, PathBuf), String> {
+        let profile = self.profile(DEFAULT_PROFILE)?;
 
-        match f.write_all(serialized.as_bytes()) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("fail to store config with error: {}", e)),
+        match profile.destinations().into_iter().next() {
+            Some(destination) => Ok((profile.source, destination.path)),
+            None => Err(format!("profile: {} has no destination", DEFAULT_PROFILE)),
         }
     }
+
+    /// All profiles by name
+    pub fn profiles(&self) -> Result<BTreeMap<String, Profile>, String> {
+        Ok(self.load()?.profiles)
+    }
+
+    pub fn profile(&self, name: &str) -> Result<Profile, String> {
+        self.profiles()?
+            .remove(name)
+            .ok_or_else(|| format!("profile: {} doesn't exist in config", name))
+    }
+
+    /// Store paths as default profile
+    pub fn store(&self, source: PathBuf, destination: PathBuf) -> Result<(), String> {
+        self.store_profile(DEFAULT_PROFILE, Profile::new(source, destination))
+    }
+
+    /// Add or replace profile, other profiles are kept
+    pub fn store_profile(&self, name: &str, profile: Profile) -> Result<(), String> {
+        let mut storage = if self.path.exists() {
+            self.load()?
+        } else {
+            ConfigStorage::default()
+        };
+        storage.profiles.insert(name.to_string(), profile);
+
+        self.write(&storage)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
 
-    use super::Config;
+    use super::*;
 
     #[test]
     fn parse() {
@@ -96,4 +1071,289 @@ mod tests {
         assert_eq!(source, PathBuf::from("/test/path/to_source"));
         assert_eq!(destination, PathBuf::from("/test/path/to_destination"));
     }
+
+    #[test]
+    fn version_and_formats() {
+        let cfg = Config::new(PathBuf::from("tests/config/test_config.yaml"));
+        assert_eq!(cfg.version(), Ok(1));
+
+        let cfg = Config::new(PathBuf::from("tests/config/test_config_profiles.toml"));
+        assert_eq!(cfg.version(), Ok(CONFIG_VERSION));
+
+        assert_eq!(Format::of(Path::new("config.TOML")), Format::Toml);
+        assert_eq!(Format::of(Path::new("config.json")), Format::Json);
+        assert_eq!(Format::of(Path::new("config.yml")), Format::Yaml);
+
+        let profiles = Config::new(PathBuf::from("tests/config/test_config_profiles.yaml"))
+            .profiles()
+            .unwrap();
+        for path in [
+            "tests/config/test_config_profiles.toml",
+            "tests/config/test_config_profiles.json",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Config::new(PathBuf::from(path)).profiles(),
+                Ok(profiles.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn store_toml() {
+        let path = PathBuf::from("tests/config/test_config_store_new.toml");
+        let cfg = Config::new(path.clone());
+
+        let mut profile = Profile::new(
+            PathBuf::from("/test/path/to_source"),
+            PathBuf::from("/test/path/to_destination"),
+        );
+        profile
+            .destinations
+            .push(Destination::new(PathBuf::from("/test/path/to_backup")));
+
+        assert_eq!(cfg.store_profile("toml", profile.clone()), Ok(()));
+        let stored = (cfg.profile("toml"), cfg.version());
+
+        if let Err(e) = fs::remove_file(&path) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert_eq!(stored, (Ok(profile), Ok(CONFIG_VERSION)));
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let path = PathBuf::from("tests/config/test_config_migrate.yaml");
+        if let Err(e) = fs::copy("tests/config/test_config.yaml", &path) {
+            panic!("Copy error: {}", e);
+        }
+
+        let cfg = Config::new(path.clone());
+        let backup = cfg.migrate().unwrap().unwrap();
+        assert_eq!(
+            backup,
+            PathBuf::from("tests/config/test_config_migrate.yaml.v1.bak")
+        );
+        assert_eq!(cfg.version(), Ok(CONFIG_VERSION));
+        assert_eq!(cfg.migrate(), Ok(None));
+        assert_eq!(
+            cfg.parse(),
+            Ok((
+                PathBuf::from("/test/path/to_source"),
+                PathBuf::from("/test/path/to_destination")
+            ))
+        );
+
+        let restore: std::io::Result<()> = (|| {
+            fs::remove_file(&path)?;
+            fs::remove_file(&backup)?;
+            Ok(())
+        })();
+        if let Err(e) = restore {
+            panic!("Restore error: {}", e);
+        }
+    }
+
+    #[test]
+    fn strict_unknown_keys() {
+        let cfg = Config::new(PathBuf::from("tests/config/test_config_unknown.yaml"));
+
+        assert_eq!(
+            cfg.unknown_keys(),
+            Ok(vec![
+                String::from("colour at line 16"),
+                String::from("profiles.home.destinations[0].format at line 15"),
+                String::from("profiles.kobo.direktion at line 6"),
+                String::from("profiles.kobo.rules[0].recursive at line 10"),
+            ])
+        );
+        assert!(cfg.profiles().is_ok());
+        assert!(cfg.with_strict(true).profiles().is_err());
+
+        let cfg = Config::new(PathBuf::from("tests/config/test_config_profiles.toml"));
+        assert_eq!(cfg.unknown_keys(), Ok(Vec::new()));
+        assert!(cfg.with_strict(true).profiles().is_ok());
+    }
+
+    #[test]
+    fn edit_keys() {
+        let path = PathBuf::from("tests/config/test_config_edit.yaml");
+        if let Err(e) = fs::copy("tests/config/test_config_profiles.yaml", &path) {
+            panic!("Copy error: {}", e);
+        }
+        let cfg = Config::new(path.clone());
+
+        assert_eq!(
+            cfg.get("profiles.home.destinations.0.formats"),
+            Ok(Some(serde_json::json!(["epub"])))
+        );
+        assert_eq!(cfg.get("profiles.home.missing"), Ok(None));
+
+        assert_eq!(
+            cfg.set("profiles.kobo.max_change", parse_value("20")),
+            Ok(())
+        );
+        assert_eq!(
+            cfg.set(
+                "profiles.home.destinations.2",
+                parse_value("{path: /test/path/to_usb}")
+            ),
+            Ok(())
+        );
+        assert!(cfg
+            .set("profiles.kobo.max_change", parse_value("many"))
+            .is_err());
+        assert!(cfg
+            .set("profiles.kobo.direktion", parse_value("push"))
+            .is_err());
+
+        assert_eq!(cfg.unset("profiles.kindle"), Ok(true));
+        assert_eq!(cfg.unset("profiles.kindle"), Ok(false));
+
+        let backup = PathBuf::from("tests/config/test_config_edit.yaml.v1.bak");
+        let edited = fs::read_to_string(&path).unwrap();
+        let position = |key: &str| edited.find(key).unwrap();
+
+        // Old config is kept, order of keys is kept
+        assert!(backup.exists());
+        assert!(position("kobo:") < position("home:"));
+        assert!(position("source:") < position("destination:"));
+
+        let profiles = cfg.profiles().unwrap();
+        assert_eq!(profiles["kobo"].max_change, 20);
+        assert_eq!(
+            profiles["kobo"].selection.include,
+            [PathBuf::from("Fiction")]
+        );
+        assert_eq!(profiles["home"].destinations().len(), 3);
+        assert!(!profiles.contains_key("kindle"));
+
+        for path in [&path, &backup].iter() {
+            if let Err(e) = fs::remove_file(path) {
+                panic!("Restore error: {}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let cfg = Config::new(PathBuf::from("tests/config/test_config_profiles.yaml"));
+        let problems = cfg.validate().unwrap();
+
+        assert!(problems.contains(&String::from(
+            "profile: kobo: source: /test/path/to_source doesn't exist"
+        )));
+        assert!(problems.contains(&String::from(
+            "profile: kobo: inbox is never filled by push or mirror direction"
+        )));
+
+        let mut profile = Profile::new(
+            PathBuf::from("tests/copy_files/local"),
+            PathBuf::from("tests/copy_files/foreign"),
+        );
+        assert_eq!(profile.problems(), Vec::<String>::new());
+        profile.max_change = 150;
+        profile.selection.patterns.push(String::from("["));
+        profile.concurrency = 0;
+        assert_eq!(profile.problems().len(), 3);
+    }
+
+    #[test]
+    fn parse_profiles() {
+        let cfg = Config::new(PathBuf::from("tests/config/test_config_profiles.yaml"));
+
+        let profiles = cfg.profiles().unwrap();
+        assert_eq!(
+            profiles.keys().collect::<Vec<_>>(),
+            [
+                &String::from("home"),
+                &String::from("kindle"),
+                &String::from("kobo")
+            ]
+        );
+
+        let kobo = cfg.profile("kobo").unwrap();
+        assert_eq!(kobo.source, PathBuf::from("/test/path/to_source"));
+        assert_eq!(
+            kobo.destinations(),
+            [Destination::new(PathBuf::from("/test/path/to_kobo"))]
+        );
+        assert_eq!(kobo.direction, Direction::Push);
+        assert_eq!(kobo.selection.include, [PathBuf::from("Fiction")]);
+        assert!(kobo.delete);
+        assert_eq!(kobo.max_change, DEFAULT_MAX_CHANGE_PERCENT);
+        assert_eq!(
+            kobo.inbox(),
+            Some(Inbox::new(PathBuf::from("Inbox"), Filing::Author))
+        );
+
+        let kindle = cfg.profile("kindle").unwrap();
+        assert_eq!(kindle.direction, Direction::Both);
+        assert_eq!(kindle.trash_retention, 7);
+        assert_eq!(kindle.inbox(), None);
+        assert_eq!(
+            kindle.rules[0],
+            DirectionRule {
+                path: String::from("Downloads"),
+                direction: Direction::Pull,
+            }
+        );
+        let directions = kindle.directions().unwrap();
+        assert_eq!(
+            directions.direction_for(&PathBuf::from("Downloads/store/book.epub")),
+            Direction::Pull
+        );
+        assert_eq!(
+            directions.direction_for(&PathBuf::from("Fiction/Tolkien/Drafts/book.epub")),
+            Direction::Ignore
+        );
+        assert_eq!(
+            directions.direction_for(&PathBuf::from("Fiction/book.epub")),
+            Direction::Both
+        );
+
+        let home = cfg.profile("home").unwrap();
+        let destinations = home.destinations();
+        assert_eq!(
+            destinations
+                .iter()
+                .map(|d| d.path.clone())
+                .collect::<Vec<_>>(),
+            [
+                PathBuf::from("/test/path/to_pocketbook"),
+                PathBuf::from("/test/path/to_backup"),
+            ]
+        );
+        assert_eq!(destinations[0].selection.formats, [String::from("epub")]);
+        assert_eq!(
+            destinations[0].selection.exclude_patterns,
+            [String::from("**/draft_*")]
+        );
+        assert_eq!(destinations[1].selection, Selection::default());
+
+        let naming = home.naming().unwrap();
+        let metadata = naming
+            .infer(&PathBuf::from("Middle-earth/02 - The Two Towers.epub"))
+            .unwrap();
+        assert_eq!(metadata.series, Some(String::from("Middle-earth")));
+        assert_eq!(metadata.series_index, Some(2));
+        assert_eq!(
+            naming
+                .infer(&PathBuf::from("Fiction/Tolkien - The Hobbit (1937).pdf"))
+                .and_then(|m| m.year),
+            Some(1937)
+        );
+
+        let filter = home.filter(&destinations[0]).unwrap();
+        assert!(filter.allows(Path::new("library"), &PathBuf::from("Fiction/book.epub")));
+        assert!(!filter.allows(Path::new("library"), &PathBuf::from("Fiction/book.pdf")));
+        assert!(!filter.allows(Path::new("library"), &PathBuf::from("Science/book.epub")));
+        let filter = home.filter(&destinations[1]).unwrap();
+        assert!(filter.allows(Path::new("library"), &PathBuf::from("Fiction/book.pdf")));
+        assert!(!filter.allows(Path::new("library"), &PathBuf::from("Science/book.pdf")));
+
+        assert!(cfg.profile(DEFAULT_PROFILE).is_err());
+    }
 }
@@ -0,0 +1,97 @@
+//! Device entity
+//!
+//! Known e-ink devices and file systems with system folders which aren't
+//! books
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Device type recognized by folders in root of side
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    /// Kobo readers, `.kobo` folder
+    Kobo,
+    /// Kindle readers, `system` folder beside `documents`
+    Kindle,
+    /// Readers with Adobe DRM, `.adobe-digital-editions` folder
+    Adobe,
+    /// Drives used by Windows, `$RECYCLE.BIN` folder
+    Windows,
+    /// Android devices, `LOST.DIR` folder
+    Android,
+}
+
+/// All known device types, their exclusions are on by default
+pub const DEVICES: [Device; 5] = [
+    Device::Kobo,
+    Device::Kindle,
+    Device::Adobe,
+    Device::Windows,
+    Device::Android,
+];
+
+impl Device {
+    /// System folders in root of device
+    pub fn system_dirs(&self) -> &'static [&'static str] {
+        match self {
+            Device::Kobo => &[".kobo", ".kobo-images"],
+            Device::Kindle => &["system"],
+            Device::Adobe => &[".adobe-digital-editions"],
+            Device::Windows => &["$RECYCLE.BIN", "System Volume Information"],
+            Device::Android => &["LOST.DIR"],
+        }
+    }
+
+    /// Root is root of device of this type
+    pub fn detect(&self, root: &Path) -> bool {
+        match self {
+            // NOTE: `system` is common folder name, Kindle has it beside
+            // `documents`
+            Device::Kindle => root.join("system").is_dir() && root.join("documents").is_dir(),
+            _ => self.system_dirs().iter().any(|dir| root.join(dir).is_dir()),
+        }
+    }
+}
+
+/// System folders of root of device types found among devices
+pub fn system_dirs(root: &Path, devices: &[Device]) -> Vec<&'static str> {
+    devices
+        .iter()
+        .filter(|device| device.detect(root))
+        .flat_map(|device| device.system_dirs().iter().cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn detect_devices() {
+        let root = PathBuf::from("tests/device");
+        let created = fs::create_dir_all(root.join(".kobo"))
+            .and_then(|_| fs::create_dir_all(root.join("system")));
+        let found = (
+            system_dirs(&root, &DEVICES),
+            system_dirs(&root, &[Device::Windows]),
+        );
+        let kindle = fs::create_dir_all(root.join("documents"))
+            .map(|_| system_dirs(&root, &[Device::Kindle]))
+            .ok();
+
+        if let Err(e) = fs::remove_dir_all(&root) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert!(created.is_ok());
+        // Lone `system` folder isn't Kindle
+        assert_eq!(found.0, [".kobo", ".kobo-images"]);
+        assert!(found.1.is_empty());
+        assert_eq!(kindle, Some(vec!["system"]));
+    }
+}
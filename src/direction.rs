@@ -0,0 +1,227 @@
+//! Direction entity
+//!
+//! Direction of synchronization for whole library and for its subtrees
+
+use std::path::Path;
+use std::str::FromStr;
+
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use crate::bookshelf::Bookshelf;
+use crate::filter::MATCH_OPTIONS;
+use crate::updater::Update;
+
+/// Direction of synchronization between source and destination
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Source to destination only, destination follows source layout
+    Push,
+    /// Destination to source only, source follows destination layout
+    Pull,
+    /// Both sides, source follows destination layout
+    #[default]
+    Both,
+    /// Destination is exact copy of source, extra books of destination go
+    /// to its trash
+    Mirror,
+    /// Not synchronized
+    Ignore,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(direction: &str) -> Result<Self, Self::Err> {
+        match direction {
+            "push" => Ok(Direction::Push),
+            "pull" => Ok(Direction::Pull),
+            "both" => Ok(Direction::Both),
+            "mirror" => Ok(Direction::Mirror),
+            "ignore" => Ok(Direction::Ignore),
+            _ => Err(format!("unknown direction: {}", direction)),
+        }
+    }
+}
+
+/// All updates in order they should run
+const UPDATES: [Update; 5] = [
+    Update::OnlyFromForeignSync,
+    Update::OnlyFromLocalSync,
+    Update::OnlyFromLocalMirror,
+    Update::OnlyFromLocal,
+    Update::OnlyFromForeign,
+];
+
+impl Direction {
+    /// Sequence of updates to run for direction
+    pub fn updates(&self) -> Vec<Update> {
+        match self {
+            Direction::Push => vec![Update::OnlyFromLocalSync, Update::OnlyFromLocal],
+            Direction::Pull => vec![Update::OnlyFromForeignSync, Update::OnlyFromForeign],
+            Direction::Both => vec![
+                Update::OnlyFromForeignSync,
+                Update::OnlyFromLocal,
+                Update::OnlyFromForeign,
+            ],
+            Direction::Mirror => vec![
+                Update::OnlyFromLocalSync,
+                Update::OnlyFromLocalMirror,
+                Update::OnlyFromLocal,
+            ],
+            Direction::Ignore => Vec::new(),
+        }
+    }
+}
+
+/// Direction of library with directions of subtrees matched by glob patterns
+///
+/// Pattern matches book by its path relative to library root or by any parent
+/// folder of it, first matched pattern wins
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Directions {
+    default: Direction,
+    rules: Vec<(Pattern, Direction)>,
+}
+
+impl Directions {
+    pub fn new(default: Direction) -> Self {
+        Directions {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn with_rule(mut self, pattern: Pattern, direction: Direction) -> Self {
+        self.rules.push((pattern, direction));
+        self
+    }
+
+    pub fn direction_for(&self, relative: &Path) -> Direction {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| {
+                relative
+                    .ancestors()
+                    .any(|p| pattern.matches_path_with(p, MATCH_OPTIONS))
+            })
+            .map(|(_, direction)| *direction)
+            .unwrap_or(self.default)
+    }
+
+    /// Updates required by any of directions, in order they should run
+    pub fn updates(&self) -> Vec<Update> {
+        let directions = self
+            .rules
+            .iter()
+            .map(|(_, direction)| direction)
+            .chain(Some(&self.default))
+            .collect::<Vec<&Direction>>();
+
+        UPDATES
+            .iter()
+            .filter(|update| directions.iter().any(|d| d.updates().contains(update)))
+            .cloned()
+            .collect()
+    }
+
+    /// Books of bookshelf placed in subtrees synchronized by update
+    pub fn apply(&self, bookshelf: &Bookshelf, update: &Update) -> Bookshelf {
+        bookshelf.filter(|b| {
+            self.direction_for(bookshelf.relative_path(b))
+                .updates()
+                .contains(update)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn directions() -> Directions {
+        Directions::new(Direction::Both)
+            .with_rule(Pattern::new("Downloads").unwrap(), Direction::Pull)
+            .with_rule(Pattern::new("Fiction/*/Drafts").unwrap(), Direction::Ignore)
+            .with_rule(Pattern::new("Fiction/**").unwrap(), Direction::Push)
+    }
+
+    #[test]
+    fn direction_for() {
+        let directions = directions();
+
+        assert_eq!(
+            directions.direction_for(Path::new("Downloads/store/book.epub")),
+            Direction::Pull
+        );
+        assert_eq!(
+            directions.direction_for(Path::new("Fiction/Tolkien/Drafts/book.epub")),
+            Direction::Ignore
+        );
+        assert_eq!(
+            directions.direction_for(Path::new("Fiction/Tolkien/book.epub")),
+            Direction::Push
+        );
+        assert_eq!(
+            directions.direction_for(Path::new("Science/book.pdf")),
+            Direction::Both
+        );
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("mirror".parse(), Ok(Direction::Mirror));
+        assert!("sideways".parse::<Direction>().is_err());
+    }
+
+    #[test]
+    fn updates() {
+        assert_eq!(
+            directions().updates(),
+            [
+                Update::OnlyFromForeignSync,
+                Update::OnlyFromLocalSync,
+                Update::OnlyFromLocal,
+                Update::OnlyFromForeign,
+            ]
+        );
+
+        assert_eq!(
+            Directions::new(Direction::Mirror).updates(),
+            Direction::Mirror.updates()
+        );
+        assert_eq!(Directions::new(Direction::Ignore).updates(), []);
+    }
+
+    #[test]
+    fn apply() {
+        let mut bs = Bookshelf::from(PathBuf::from("/library"));
+        bs.add(crate::book::Book::from(PathBuf::from(
+            "/library/Downloads/store_book.epub",
+        )));
+        bs.add(crate::book::Book::from(PathBuf::from(
+            "/library/Fiction/Tolkien/hobbit.epub",
+        )));
+        bs.add(crate::book::Book::from(PathBuf::from(
+            "/library/Science/math.pdf",
+        )));
+
+        let names = |bs: Bookshelf| {
+            bs.iter()
+                .map(|b| b.get_name().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            names(directions().apply(&bs, &Update::OnlyFromLocal)),
+            [String::from("hobbit.epub"), String::from("math.pdf")]
+        );
+        assert_eq!(
+            names(directions().apply(&bs, &Update::OnlyFromForeign)),
+            [String::from("math.pdf"), String::from("store_book.epub")]
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Filter entity
+//!
+//! Select part of library to synchronize
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use log::trace;
+
+use crate::book::Book;
+use crate::bookshelf::Bookshelf;
+use crate::metadata::Metadata;
+
+pub(crate) const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Selection rule, paths and patterns are relative to library root
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// Books inside one of folders
+    Include(Vec<PathBuf>),
+    /// Books outside of all folders
+    Exclude(Vec<PathBuf>),
+    /// Books matching one of glob patterns
+    Patterns(Vec<Pattern>),
+    /// Books matching none of glob patterns
+    ExcludePatterns(Vec<Pattern>),
+    /// Books in one of languages
+    Languages(Vec<String>),
+    /// Books of one of formats (file extensions)
+    Formats(Vec<String>),
+    /// Books with author name containing one of names
+    Authors(Vec<String>),
+}
+
+impl Rule {
+    fn needs_metadata(&self) -> bool {
+        matches!(self, Rule::Languages(_) | Rule::Authors(_))
+    }
+
+    fn allows(&self, relative: &Path, metadata: &Metadata) -> bool {
+        let any_equal = |values: &[String], value: &Option<String>| match value {
+            Some(value) => values.iter().any(|v| v.eq_ignore_ascii_case(value)),
+            None => false,
+        };
+
+        match self {
+            Rule::Include(folders) => folders.iter().any(|f| relative.starts_with(f)),
+            Rule::Exclude(folders) => !folders.iter().any(|f| relative.starts_with(f)),
+            Rule::Patterns(patterns) => patterns
+                .iter()
+                .any(|p| p.matches_path_with(relative, MATCH_OPTIONS)),
+            Rule::ExcludePatterns(patterns) => !patterns
+                .iter()
+                .any(|p| p.matches_path_with(relative, MATCH_OPTIONS)),
+            Rule::Languages(languages) => any_equal(languages, &metadata.language),
+            Rule::Formats(formats) => any_equal(formats, &metadata.format),
+            Rule::Authors(names) => metadata.authors.iter().any(|author| {
+                let author = author.to_string().to_lowercase();
+                names.iter().any(|n| author.contains(&n.to_lowercase()))
+            }),
+        }
+    }
+}
+
+/// Books allowed by all rules, filter without rules selects everything
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    rules: Vec<Rule>,
+}
+
+impl Filter {
+    pub fn new(include: Vec<PathBuf>, exclude: Vec<PathBuf>) -> Self {
+        let filter = Filter::default();
+
+        let filter = if include.is_empty() {
+            filter
+        } else {
+            filter.with(Rule::Include(include))
+        };
+
+        if exclude.is_empty() {
+            filter
+        } else {
+            filter.with(Rule::Exclude(exclude))
+        }
+    }
+
+    pub fn with(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Filter allowing books allowed by both filters
+    pub fn and(mut self, other: Filter) -> Self {
+        self.rules.extend(other.rules);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Check path relative to library root, metadata rules read book inside
+    /// root
+    pub fn allows(&self, root: &Path, relative: &Path) -> bool {
+        self.allows_book(relative, &Book::from(root.join(relative)))
+    }
+
+    fn allows_book(&self, relative: &Path, book: &Book) -> bool {
+        let metadata = if self.rules.iter().any(Rule::needs_metadata) {
+            book.metadata()
+        } else {
+            Metadata {
+                format: relative
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| e.to_lowercase()),
+                ..Metadata::default()
+            }
+        };
+
+        self.rules.iter().all(|r| r.allows(relative, &metadata))
+    }
+
+    /// Books of bookshelf allowed by filter
+    pub fn apply(&self, bookshelf: Bookshelf) -> Bookshelf {
+        if self.is_empty() {
+            return bookshelf;
+        }
+
+        self.select(&bookshelf).into_owned()
+    }
+
+    /// Books of borrowed bookshelf allowed by filter, bookshelf stays borrowed
+    /// if filter has no rules
+    pub fn select<'a>(&self, bookshelf: &'a Bookshelf) -> Cow<'a, Bookshelf> {
+        self.select_by(bookshelf, |b| bookshelf.relative_path(b).to_path_buf())
+    }
+
+    /// Books of borrowed bookshelf allowed by filter, rules of paths check
+    /// path given for book
+    pub fn select_by<'a, P>(&self, bookshelf: &'a Bookshelf, path: P) -> Cow<'a, Bookshelf>
+    where
+        P: Fn(&Book) -> PathBuf,
+    {
+        if self.is_empty() {
+            return Cow::Borrowed(bookshelf);
+        }
+
+        Cow::Owned(bookshelf.filter(|b| {
+            let allowed = self.allows_book(&path(b), b);
+            if !allowed {
+                trace!("Skip by selection rules: {}", b.get_path().display());
+            }
+            allowed
+        }))
+    }
+}
+
+/// Compile glob patterns of config
+pub fn patterns(patterns: &[String]) -> Result<Vec<Pattern>, String> {
+    patterns
+        .iter()
+        .map(|p| {
+            Pattern::new(p).map_err(|e| format!("fail to parse pattern: {} with error: {}", p, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::Indexer;
+
+    #[test]
+    fn allows() {
+        let filter = Filter::new(
+            vec![PathBuf::from("Fiction"), PathBuf::from("Science")],
+            vec![PathBuf::from("Fiction/Drafts")],
+        );
+
+        assert!(filter.allows(Path::new("library"), Path::new("Fiction/book.epub")));
+        assert!(filter.allows(Path::new("library"), Path::new("Science/Math/book.pdf")));
+        assert!(!filter.allows(Path::new("library"), Path::new("Fiction/Drafts/book.epub")));
+        assert!(!filter.allows(Path::new("library"), Path::new("Other/book.epub")));
+        assert!(Filter::default().allows(Path::new("library"), Path::new("Other/book.epub")));
+    }
+
+    #[test]
+    fn allows_patterns_and_formats() {
+        let filter = Filter::default()
+            .with(Rule::Patterns(
+                patterns(&[String::from("Fiction/**/*.epub")]).unwrap(),
+            ))
+            .with(Rule::ExcludePatterns(
+                patterns(&[String::from("**/draft_*")]).unwrap(),
+            ));
+
+        assert!(filter.allows(Path::new("library"), Path::new("Fiction/Tolkien/book.EPUB")));
+        assert!(!filter.allows(
+            Path::new("library"),
+            Path::new("Fiction/Tolkien/draft_book.epub")
+        ));
+        assert!(!filter.allows(Path::new("library"), Path::new("Fiction/book.pdf")));
+        assert!(!filter.allows(Path::new("library"), Path::new("Science/book.epub")));
+
+        let filter = Filter::default().with(Rule::Formats(vec![String::from("pdf")]));
+        assert!(filter.allows(Path::new("library"), Path::new("Science/book.pdf")));
+        assert!(!filter.allows(Path::new("library"), Path::new("Science/book.epub")));
+
+        assert!(patterns(&[String::from("[")]).is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let bs = Indexer::new(PathBuf::from("tests/copy_files/local")).index();
+
+        let ixer_res: Vec<_> = Filter::new(vec![], vec![PathBuf::from("test")])
+            .apply(bs)
+            .iter()
+            .map(|b| b.get_name().to_string())
+            .collect();
+        assert_eq!(
+            ixer_res,
+            [
+                String::from("file_one.txt"),
+                String::from("file_three.txt"),
+                String::from("file_two.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_metadata() {
+        let bs = Indexer::new(PathBuf::from("tests/metadata")).index();
+
+        let filter = Filter::default()
+            .with(Rule::Languages(vec![String::from("EN")]))
+            .and(Filter::default().with(Rule::Authors(vec![String::from("tolkien")])));
+        assert_eq!(filter.apply(bs.clone()).len(), 1);
+
+        let filter = Filter::default().with(Rule::Languages(vec![String::from("ru")]));
+        assert!(filter.apply(bs).is_empty());
+
+        // Book is read inside root, not by path relative to current dir
+        let filter = Filter::default().with(Rule::Languages(vec![String::from("en")]));
+        assert!(filter.allows(Path::new("tests/metadata"), Path::new("hobbit.fb2")));
+    }
+}
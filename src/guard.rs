@@ -0,0 +1,262 @@
+//! Guard entity
+//!
+//! Safety checks against mass changes of library by wrong sync setup
+
+use std::cell::Cell;
+use std::path::Path;
+
+use crate::bookshelf::Bookshelf;
+use crate::indexer::IndexReport;
+use crate::state::SyncState;
+use crate::updater::{BookStatus, BookTransferStatus};
+
+/// Part of books allowed to change by updates of destination if not
+/// configured
+pub const DEFAULT_MAX_CHANGE_PERCENT: u8 = 50;
+
+pub struct Guard {
+    max_change_percent: u8,
+    confirmed: bool,
+    /// Allow side without books which was synchronized before
+    empty_confirmed: bool,
+    /// Refuse sides with entries missing from index
+    complete_index: bool,
+    /// Changes of plans allowed before, they count towards limit
+    changes: Cell<usize>,
+}
+
+impl Guard {
+    /// Confirmed guard allows changes over limit
+    pub fn new(max_change_percent: u8, confirmed: bool) -> Self {
+        Guard {
+            max_change_percent,
+            confirmed,
+            empty_confirmed: false,
+            complete_index: false,
+            changes: Cell::new(0),
+        }
+    }
+
+    /// Allow sync of side indexed to zero books, it is confirmed apart from
+    /// changes over limit
+    pub fn with_empty_confirmed(mut self, empty_confirmed: bool) -> Self {
+        self.empty_confirmed = empty_confirmed;
+        self
+    }
+
+    /// Refuse sync of side which isn't indexed completely, its unreadable
+    /// books look deleted
+    pub fn with_complete_index(mut self, complete_index: bool) -> Self {
+        self.complete_index = complete_index;
+        self
+    }
+
+    /// Refuse filesystem roots and sides placed one inside other
+    pub fn check_paths(&self, local: &Path, foreign: &Path) -> Result<(), String> {
+        let canonical = |path: &Path| {
+            path.canonicalize().map_err(|e| {
+                format!(
+                    "fail to resolve path: {} with error: {}",
+                    path.to_str().unwrap(),
+                    e
+                )
+            })
+        };
+
+        let local = canonical(local)?;
+        let foreign = canonical(foreign)?;
+
+        for path in [&local, &foreign].iter() {
+            if path.parent().is_none() {
+                return Err(format!(
+                    "path: {} is filesystem root",
+                    path.to_str().unwrap()
+                ));
+            }
+        }
+
+        if local.starts_with(&foreign) || foreign.starts_with(&local) {
+            return Err(format!(
+                "paths: {} and {} are inside one another",
+                local.to_str().unwrap(),
+                foreign.to_str().unwrap()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Refuse side indexed to zero books when books were synchronized before
+    pub fn check_index(
+        &self,
+        local: &Bookshelf,
+        foreign: &Bookshelf,
+        state: &SyncState,
+    ) -> Result<(), String> {
+        if self.empty_confirmed || state.is_empty() {
+            return Ok(());
+        }
+
+        match [local, foreign].iter().find(|bs| bs.is_empty()) {
+            Some(empty) => Err(format!(
+                "path: {} has no books, but it was synchronized before",
+                empty.get_path().to_str().unwrap()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuse incomplete index if complete one is required, confirmation
+    /// doesn't allow it
+    pub fn check_report(&self, report: &IndexReport) -> Result<(), String> {
+        match report.get_errors().first() {
+            Some(first) if self.complete_index => Err(format!(
+                "index is incomplete, {} entries failed, first: {} with error: {}",
+                report.get_errors().len(),
+                first.path.display(),
+                first.error
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuse plan changing more than allowed part of all books together
+    /// with plans allowed before
+    pub fn check_plan(&self, plan: &[BookStatus], books: usize) -> Result<(), String> {
+        if self.confirmed || books == 0 {
+            return Ok(());
+        }
+
+        let changes = self.changes.get()
+            + plan
+                .iter()
+                .filter(|b| !matches!(b.get_status(), BookTransferStatus::Error(_)))
+                .count();
+
+        if changes * 100 > books * usize::from(self.max_change_percent) {
+            Err(format!(
+                "sync changes {} of {} books, more than {}% allowed",
+                changes, books, self.max_change_percent
+            ))
+        } else {
+            self.changes.set(changes);
+            Ok(())
+        }
+    }
+}
+
+impl Default for Guard {
+    fn default() -> Self {
+        Guard::new(DEFAULT_MAX_CHANGE_PERCENT, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::book::Book;
+    use crate::indexer::Indexer;
+
+    #[test]
+    fn paths_check() {
+        let guard = Guard::default();
+
+        assert_eq!(
+            guard.check_paths(
+                &PathBuf::from("tests/scan_area/local"),
+                &PathBuf::from("tests/scan_area/foreign")
+            ),
+            Ok(())
+        );
+        assert!(guard
+            .check_paths(
+                &PathBuf::from("tests/scan_area"),
+                &PathBuf::from("tests/scan_area/foreign")
+            )
+            .is_err());
+        assert!(guard
+            .check_paths(&PathBuf::from("/"), &PathBuf::from("tests/scan_area"))
+            .is_err());
+        assert!(guard
+            .check_paths(
+                &PathBuf::from("tests/scan_area/local"),
+                &PathBuf::from("tests/scan_area/missing")
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn index_check() {
+        let mut local = Bookshelf::from(PathBuf::from("local"));
+        local.add(Book::new(String::from("file_one.txt")));
+        let foreign = Bookshelf::from(PathBuf::from("foreign"));
+
+        let state = SyncState::new(PathBuf::new());
+        assert_eq!(
+            Guard::default().check_index(&local, &foreign, &state),
+            Ok(())
+        );
+
+        let mut state = SyncState::new(PathBuf::new());
+        state.record(&local);
+        assert!(Guard::default()
+            .check_index(&local, &foreign, &state)
+            .is_err());
+        // Confirmed changes over limit don't allow empty side
+        assert!(Guard::new(DEFAULT_MAX_CHANGE_PERCENT, true)
+            .check_index(&local, &foreign, &state)
+            .is_err());
+        assert_eq!(
+            Guard::default()
+                .with_empty_confirmed(true)
+                .check_index(&local, &foreign, &state),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn report_check() {
+        let (_, report) = Indexer::new(PathBuf::from("tests/missing")).scan();
+
+        assert_eq!(Guard::default().check_report(&report), Ok(()));
+        assert!(Guard::new(DEFAULT_MAX_CHANGE_PERCENT, true)
+            .with_complete_index(true)
+            .check_report(&report)
+            .is_err());
+        assert_eq!(
+            Guard::default()
+                .with_complete_index(true)
+                .check_report(&IndexReport::new()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn plan_check() {
+        let plan = [
+            BookStatus::new(
+                &Book::new(String::from("file_one.txt")),
+                PathBuf::new(),
+                BookTransferStatus::Copied,
+            ),
+            BookStatus::new(
+                &Book::new(String::from("file_two.txt")),
+                PathBuf::new(),
+                BookTransferStatus::Error(String::from("fail")),
+            ),
+        ];
+
+        assert_eq!(Guard::new(50, false).check_plan(&plan, 2), Ok(()));
+        assert!(Guard::new(49, false).check_plan(&plan, 2).is_err());
+        assert_eq!(Guard::new(0, true).check_plan(&plan, 2), Ok(()));
+        assert_eq!(Guard::new(0, false).check_plan(&[], 0), Ok(()));
+
+        // Plans of destination count together
+        let guard = Guard::new(50, false);
+        assert_eq!(guard.check_plan(&plan, 4), Ok(()));
+        assert_eq!(guard.check_plan(&plan, 4), Ok(()));
+        assert!(guard.check_plan(&plan, 4).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Inbox entity
+//!
+//! Local folder receiving books found only on device
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::book::Book;
+use crate::utility::path_safe;
+
+/// Folder name for books without metadata used by filing
+const UNKNOWN: &str = "Unknown";
+
+/// Subfolders of inbox filled by book metadata
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Filing {
+    /// All books in inbox root
+    #[default]
+    Flat,
+    /// `<author>/<book>`
+    Author,
+    /// `<author>/<series>/<book>`, books out of series are filed by author
+    Series,
+    /// `<language>/<book>`
+    Language,
+    /// `<format>/<book>`
+    Format,
+}
+
+/// Folder name from metadata value, path separators are replaced
+fn folder(value: Option<String>) -> String {
+    match value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    {
+        Some(value) => path_safe(&value),
+        None => UNKNOWN.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inbox {
+    path: PathBuf,
+    filing: Filing,
+}
+
+impl Inbox {
+    /// Inbox at path relative to local library root
+    pub fn new(path: PathBuf, filing: Filing) -> Self {
+        Inbox { path, filing }
+    }
+
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Path of book inside local library, relative to library root
+    pub fn path_for(&self, book: &Book) -> PathBuf {
+        let mut path = self.path.clone();
+
+        if self.filing != Filing::Flat {
+            let metadata = book.metadata();
+            let author = || folder(metadata.authors.first().map(|a| a.to_string()));
+
+            match self.filing {
+                Filing::Flat => {}
+                Filing::Author => path.push(author()),
+                Filing::Series => {
+                    path.push(author());
+                    if metadata.series.is_some() {
+                        path.push(folder(metadata.series.clone()));
+                    }
+                }
+                Filing::Language => path.push(folder(metadata.language.clone())),
+                Filing::Format => path.push(folder(metadata.format.clone())),
+            }
+        }
+
+        path.push(book.get_name());
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_for() {
+        let book = Book::from(PathBuf::from("tests/metadata/hobbit.fb2"));
+
+        assert_eq!(
+            Inbox::new(PathBuf::from("Inbox"), Filing::Flat).path_for(&book),
+            PathBuf::from("Inbox/hobbit.fb2")
+        );
+        assert_eq!(
+            Inbox::new(PathBuf::from("Inbox"), Filing::Series).path_for(&book),
+            PathBuf::from("Inbox/John Ronald Reuel Tolkien/Middle-earth/hobbit.fb2")
+        );
+        assert_eq!(
+            Inbox::new(PathBuf::from("Inbox"), Filing::Language).path_for(&book),
+            PathBuf::from("Inbox/en/hobbit.fb2")
+        );
+
+        let book = Book::from(PathBuf::from("tests/iterate/file_one.txt"));
+        assert_eq!(
+            Inbox::new(PathBuf::from("Inbox"), Filing::Author).path_for(&book),
+            PathBuf::from("Inbox/Unknown/file_one.txt")
+        );
+    }
+}
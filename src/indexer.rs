@@ -2,55 +2,710 @@
 //!
 //! Create file index, parsing through walking directory
 
-use std::path::PathBuf;
-use walkdir::{DirEntry, WalkDir};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use log::{debug, trace, warn};
+use rayon::prelude::*;
+use rayon::Scope;
+use serde::{Deserialize, Serialize};
 
 use crate::book::Book;
 use crate::bookshelf::Bookshelf;
+use crate::cache::{self, DirRecord, FileRecord, IndexCache};
+use crate::device::{system_dirs, Device, DEVICES};
+use crate::layered::LIBRARY_CONFIG;
+use crate::metadata::Metadata;
+use crate::naming::Naming;
+use crate::state::STATE_FILE;
+use crate::trash::TRASH_DIR;
+use crate::utility::error_kind_name;
 
 pub struct Indexer {
     path: PathBuf,
+    naming: Naming,
+    /// Dir of index caches, index is read from scratch if not set
+    cache: Option<PathBuf>,
+    links: Links,
+    options: WalkOptions,
+}
+
+/// Entries of sync itself, they are never indexed
+const OWN_ENTRIES: [&str; 3] = [TRASH_DIR, STATE_FILE, LIBRARY_CONFIG];
+
+/// Entries walked by indexer besides visible files and folders
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalkOptions {
+    /// Index hidden files and walk hidden folders
+    pub hidden: bool,
+    /// Depth of folders below root which are walked, 0 is root only, all
+    /// folders if not set
+    pub max_depth: Option<usize>,
+    /// Device types whose system folders are skipped if side is their device
+    pub devices: Vec<Device>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            hidden: false,
+            max_depth: None,
+            devices: DEVICES.to_vec(),
+        }
+    }
+}
+
+/// Handling of symbolic links of side, broken links are reported by any
+/// policy
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Links {
+    /// Links are not indexed
+    #[default]
+    Ignore,
+    /// Links are part of side: linked books are indexed, linked folders are
+    /// walked, links to folder of own walk are reported as loops
+    Follow,
+    /// Targets of links are indexed as by `Follow`, but they are only
+    /// copied: linked books aren't moved or trashed by sync
+    Copy,
+}
+
+impl FromStr for Links {
+    type Err = String;
+
+    fn from_str(links: &str) -> Result<Self, Self::Err> {
+        match links {
+            "ignore" => Ok(Links::Ignore),
+            "follow" => Ok(Links::Follow),
+            "copy" => Ok(Links::Copy),
+            _ => Err(format!("unknown links policy: {}", links)),
+        }
+    }
+}
+
+/// Entry which can't be indexed, its books are missing from index
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IndexError {
+    pub path: PathBuf,
+    pub error_kind: String,
+    pub error: String,
+}
+
+impl IndexError {
+    fn new(path: PathBuf, error: &io::Error) -> Self {
+        IndexError {
+            path,
+            error_kind: error_kind_name(error.kind()),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Errors of walk, index without errors is complete
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct IndexReport {
+    errors: Vec<IndexError>,
+    special: Vec<PathBuf>,
+}
+
+impl IndexReport {
+    pub fn new() -> Self {
+        IndexReport::default()
+    }
+
+    pub fn get_errors(&self) -> &Vec<IndexError> {
+        &self.errors
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Special files like FIFOs and sockets, they are skipped as not books
+    pub fn get_special(&self) -> &Vec<PathBuf> {
+        &self.special
+    }
+
+    pub fn merge(&mut self, other: IndexReport) {
+        self.errors.extend(other.errors);
+        self.special.extend(other.special);
+    }
+}
+
+/// Decision of visitor of `Indexer::index_with` about book file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visit {
+    /// Add book to index with changes of visitor, like filled metadata
+    Keep,
+    /// Leave file out of index
+    Skip,
+    /// Leave file out and stop walk, books kept before are indexed
+    Stop,
+}
+
+/// Book file with record of new cache, or error of file
+type Found = Result<(Book, Option<(PathBuf, FileRecord)>), IndexError>;
+
+/// Found by walk: books, records of new cache and errors
+struct Walked {
+    books: Vec<Book>,
+    cache: IndexCache,
+    errors: Vec<IndexError>,
+    special: Vec<PathBuf>,
+}
+
+/// Walk of side shared by its directories
+struct Walk<'s> {
+    /// Cache of previous scan, unchanged directories aren't read again
+    old: Option<&'s IndexCache>,
+    /// System folders of devices detected on root
+    system: Vec<&'static str>,
+    walked: Mutex<Walked>,
+}
+
+/// Directory read by walk: record of cache and entries to index
+struct Listed {
+    relative: PathBuf,
+    record: DirRecord,
+    files: Vec<String>,
+    subdirs: Vec<PathBuf>,
+    errors: Vec<IndexError>,
+    special: Vec<PathBuf>,
+}
+
+impl Walk<'_> {
+    /// Add found books of directory chosen by visitor, records of files
+    /// are cached for all of them
+    ///
+    /// Returns false if visitor stopped walk, books after stop aren't added
+    fn gather<F>(&self, mut listed: Listed, found: Vec<Found>, visit: &mut F) -> bool
+    where
+        F: FnMut(&mut Book) -> Visit,
+    {
+        let mut walked = self.walked.lock().unwrap();
+        let mut walking = true;
+
+        for found in found {
+            match found {
+                Ok((mut book, record)) => {
+                    if let Some((relative, record)) = record {
+                        walked.cache.add_file(relative, record);
+                    }
+                    if !walking {
+                        continue;
+                    }
+                    match visit(&mut book) {
+                        Visit::Keep => walked.books.push(book),
+                        Visit::Skip => trace!("Skip by visitor: {}", book.get_path().display()),
+                        Visit::Stop => {
+                            debug!("Walk stopped by visitor: {}", book.get_path().display());
+                            walking = false;
+                        }
+                    }
+                }
+                Err(error) => listed.errors.push(error),
+            }
+        }
+        // NOTE: directory with errors is read again by next scan
+        if self.old.is_some() && listed.errors.is_empty() {
+            walked.cache.add_dir(listed.relative, listed.record);
+        }
+        walked.errors.append(&mut listed.errors);
+        walked.special.append(&mut listed.special);
+
+        walking
+    }
 }
 
 impl Indexer {
     pub fn new(path: PathBuf) -> Self {
-        Indexer { path }
+        Indexer {
+            path,
+            naming: Naming::default(),
+            cache: None,
+            links: Links::default(),
+            options: WalkOptions::default(),
+        }
+    }
+
+    /// Fill metadata of indexed books from their names
+    pub fn with_naming(mut self, naming: Naming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Keep index in cache of dir, only changed directories and files are
+    /// read by next scan
+    pub fn with_cache(mut self, dir: PathBuf) -> Self {
+        self.cache = Some(dir);
+        self
+    }
+
+    /// Index symbolic links by policy, links are ignored if not set
+    pub fn with_links(mut self, links: Links) -> Self {
+        self.links = links;
+        self
+    }
+
+    /// Walk hidden entries, limit depth or skip system folders of other
+    /// devices
+    pub fn with_options(mut self, options: WalkOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn book(&self, path: &Path) -> Book {
+        let book = Book::from(path.to_path_buf());
+
+        match path
+            .strip_prefix(&self.path)
+            .ok()
+            .and_then(|relative| self.naming.infer(relative))
+        {
+            Some(metadata) => {
+                trace!("Naming rule matched: {}", path.display());
+                book.with_metadata(metadata)
+            }
+            None => book,
+        }
+    }
+
+    /// Entry isn't indexed: own file of sync, hidden entry or system folder
+    /// of device
+    fn skipped(&self, path: &Path, name: &str, system: &[&str]) -> bool {
+        // NOTE: not index books moved to trash and files of sync
+        if OWN_ENTRIES.contains(&name) {
+            trace!("Skip own entry: {}", path.display());
+            true
+        } else if system.contains(&name) {
+            debug!("Skip system folder of device: {}", path.display());
+            true
+        // NOTE: not index entries begins with `.` unless asked
+        } else if !self.options.hidden && name.starts_with('.') {
+            trace!("Skip hidden entry: {}", path.display());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Entries of record which are indexed, system folders are skipped in
+    /// root only
+    fn allowed(&self, dir: &Path, record: &DirRecord, system: &[&str]) -> DirRecord {
+        let allowed = |names: &Vec<String>| {
+            names
+                .iter()
+                .filter(|name| !self.skipped(&dir.join(name), name, system))
+                .cloned()
+                .collect::<Vec<String>>()
+        };
+
+        DirRecord {
+            mtime: record.mtime,
+            dirs: allowed(&record.dirs),
+            files: allowed(&record.files),
+            links: allowed(&record.links),
+            special: allowed(&record.special),
+        }
+    }
+
+    /// Names of all entries of directory, mtime of record isn't set
+    fn list(&self, dir: &Path, errors: &mut Vec<IndexError>) -> Option<DirRecord> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Skip unreadable dir: {}: {}", dir.display(), e);
+                errors.push(IndexError::new(dir.to_path_buf(), &e));
+                return None;
+            }
+        };
+
+        let mut record = DirRecord::default();
+        for entry in entries {
+            let (entry, file_type) = match entry.and_then(|e| e.file_type().map(|t| (e, t))) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Skip unreadable entry of {}: {}", dir.display(), e);
+                    errors.push(IndexError::new(dir.to_path_buf(), &e));
+                    continue;
+                }
+            };
+            // NOTE: lossy name would point to other path, such entry can't be indexed
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(name) => {
+                    let path = entry.path();
+                    warn!("Skip entry with invalid name: {}", path.display());
+                    errors.push(IndexError {
+                        error_kind: String::from("invalid_name"),
+                        error: format!("name isn't valid UTF-8: {}", name.to_string_lossy()),
+                        path,
+                    });
+                    continue;
+                }
+            };
+
+            if file_type.is_dir() {
+                record.dirs.push(name);
+            } else if file_type.is_file() {
+                record.files.push(name);
+            } else if file_type.is_symlink() {
+                record.links.push(name);
+            } else {
+                record.special.push(name);
+            }
+        }
+
+        Some(record)
     }
 
+    /// Folder which is walked by own walk, followed link to it is loop
+    fn ancestor(path: &Path, ancestors: &[PathBuf]) -> Option<PathBuf> {
+        fs::canonicalize(path)
+            .ok()
+            .filter(|target| ancestors.contains(target))
+    }
+
+    /// Sort links of directory by policy: linked books are added to files,
+    /// linked folders to walk are returned
+    fn resolve(
+        &self,
+        dir: &Path,
+        record: &DirRecord,
+        ancestors: &[PathBuf],
+        files: &mut Vec<String>,
+        errors: &mut Vec<IndexError>,
+        special: &mut Vec<PathBuf>,
+    ) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+
+        for name in record.special.iter() {
+            warn!("Skip special file: {}", dir.join(name).display());
+            special.push(dir.join(name));
+        }
+
+        for name in record.links.iter() {
+            let path = dir.join(name);
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    warn!("Skip broken link: {}: {}", path.display(), e);
+                    errors.push(IndexError::new(path, &e));
+                    continue;
+                }
+            };
+
+            match self.links {
+                Links::Ignore => trace!("Skip link: {}", path.display()),
+                Links::Follow | Links::Copy if metadata.is_file() => files.push(name.clone()),
+                Links::Follow | Links::Copy if metadata.is_dir() => {
+                    match Indexer::ancestor(&path, ancestors) {
+                        Some(target) => {
+                            warn!("Skip link loop: {}", path.display());
+                            errors.push(IndexError {
+                                error_kind: String::from("link_loop"),
+                                error: format!("link to folder of own walk: {}", target.display()),
+                                path,
+                            });
+                        }
+                        None => dirs.push(path),
+                    }
+                }
+                _ => {
+                    warn!("Skip special file: {}", path.display());
+                    special.push(path);
+                }
+            }
+        }
+
+        dirs
+    }
+
+    /// Book of file, record of cache is reused if file isn't changed
+    fn cached_book(&self, path: PathBuf, relative: PathBuf, old: &IndexCache) -> Option<Found> {
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!("Skip unreadable file: {}: {}", path.display(), e);
+                return Some(Err(IndexError::new(path, &e)));
+            }
+            _ => {
+                trace!("Skip vanished file: {}", path.display());
+                return None;
+            }
+        };
+        let (size, mtime) = (metadata.len(), cache::mtime(&metadata));
+
+        let record = match old.file(&relative, size, mtime) {
+            Some(record) => record.clone(),
+            None => FileRecord {
+                size,
+                mtime,
+                hash: None,
+                metadata: Metadata::read(&path),
+            },
+        };
+
+        let book = self.book(&path).with_embedded(record.metadata.clone());
+        let book = match record.hash {
+            Some(hash) => book.with_hash(hash),
+            None => book,
+        };
+        Some(Ok((book, Some((relative, record)))))
+    }
+
+    /// Read directory or take its record cached by previous scan, entries
+    /// are sorted by names
+    ///
+    /// Ancestors are resolved folders of walk, they are tracked only when
+    /// links aren't ignored
+    fn read(&self, dir: &Path, ancestors: &[PathBuf], walk: &Walk) -> Result<Listed, IndexError> {
+        let relative = dir.strip_prefix(&self.path).unwrap_or(dir).to_path_buf();
+        let mtime = fs::metadata(dir).map(|m| cache::mtime(&m)).unwrap_or(0);
+
+        let mut errors = Vec::new();
+        let cached = walk.old.and_then(|old| old.dir(&relative, mtime));
+        let mut record = match cached {
+            Some(record) => record.clone(),
+            None => match self.list(dir, &mut errors) {
+                Some(record) => record,
+                None => return Err(errors.remove(0)),
+            },
+        };
+        record.mtime = mtime;
+
+        // NOTE: cache keeps all entries, so changed options apply to cached
+        // directories too
+        let depth = relative.components().count();
+        let system: &[&str] = if depth == 0 { &walk.system } else { &[] };
+        let listing = self.allowed(dir, &record, system);
+        let (mut files, mut special) = (listing.files.clone(), Vec::new());
+        let mut subdirs = self.resolve(
+            dir,
+            &listing,
+            ancestors,
+            &mut files,
+            &mut errors,
+            &mut special,
+        );
+        subdirs.splice(0..0, listing.dirs.iter().map(|name| dir.join(name)));
+
+        if self.options.max_depth.is_some_and(|max| depth >= max) {
+            if !subdirs.is_empty() {
+                trace!("Skip folders below max depth: {}", dir.display());
+            }
+            subdirs.clear();
+        }
+        files.sort();
+        subdirs.sort();
+
+        Ok(Listed {
+            relative,
+            record,
+            files,
+            subdirs,
+            errors,
+            special,
+        })
+    }
+
+    /// Book of file of walked directory, cached walk reuses record of file
+    fn found(&self, dir: &Path, listed: &Listed, name: &str, walk: &Walk) -> Option<Found> {
+        match walk.old {
+            Some(old) => self.cached_book(dir.join(name), listed.relative.join(name), old),
+            None => Some(Ok((self.book(&dir.join(name)), None))),
+        }
+    }
+
+    /// Ancestors of subdirectory of walk
+    fn descend(&self, path: &Path, ancestors: &[PathBuf]) -> Vec<PathBuf> {
+        let mut ancestors = ancestors.to_vec();
+        if self.links != Links::Ignore {
+            ancestors.extend(fs::canonicalize(path).ok());
+        }
+        ancestors
+    }
+
+    /// Read directory, subdirectories are walked by other tasks of scope,
+    /// directories unchanged since cached scan aren't read
+    fn walk<'s>(
+        &'s self,
+        scope: &Scope<'s>,
+        dir: PathBuf,
+        ancestors: Vec<PathBuf>,
+        walk: &'s Walk<'_>,
+    ) {
+        let listed = match self.read(&dir, &ancestors, walk) {
+            Ok(listed) => listed,
+            Err(error) => {
+                walk.walked.lock().unwrap().errors.push(error);
+                return;
+            }
+        };
+
+        for path in listed.subdirs.iter().cloned() {
+            let ancestors = self.descend(&path, &ancestors);
+            scope.spawn(move |scope| self.walk(scope, path, ancestors, walk));
+        }
+
+        let found = listed
+            .files
+            .par_iter()
+            .filter_map(|name| self.found(&dir, &listed, name, walk))
+            .collect::<Vec<Found>>();
+        walk.gather(listed, found, &mut |_: &mut Book| Visit::Keep);
+    }
+
+    /// Read directory and walk its subdirectories one by one, files of
+    /// directory are visited before subdirectories in order of names
+    ///
+    /// Returns false if visitor stopped walk
+    fn walk_with<F>(&self, dir: PathBuf, ancestors: &[PathBuf], walk: &Walk, visit: &mut F) -> bool
+    where
+        F: FnMut(&mut Book) -> Visit,
+    {
+        let listed = match self.read(&dir, ancestors, walk) {
+            Ok(listed) => listed,
+            Err(error) => {
+                walk.walked.lock().unwrap().errors.push(error);
+                return true;
+            }
+        };
+
+        let found = listed
+            .files
+            .iter()
+            .filter_map(|name| self.found(&dir, &listed, name, walk))
+            .collect::<Vec<Found>>();
+        let subdirs = listed.subdirs.clone();
+        if !walk.gather(listed, found, visit) {
+            return false;
+        }
+
+        subdirs.into_iter().all(|path| {
+            let ancestors = self.descend(&path, ancestors);
+            self.walk_with(path, &ancestors, walk, visit)
+        })
+    }
+
+    /// Walk of side which reuses cache of previous scan
+    fn start<'s>(&self, old: Option<&'s IndexCache>) -> Walk<'s> {
+        Walk {
+            old,
+            system: system_dirs(&self.path, &self.options.devices),
+            walked: Mutex::new(Walked {
+                books: Vec::new(),
+                cache: IndexCache::new(self.path.clone()),
+                errors: Vec::new(),
+                special: Vec::new(),
+            }),
+        }
+    }
+
+    /// Resolved root when links aren't ignored
+    fn ancestors(&self) -> Vec<PathBuf> {
+        match self.links {
+            Links::Ignore => Vec::new(),
+            _ => fs::canonicalize(&self.path).into_iter().collect(),
+        }
+    }
+
+    /// Books and report of done walk, new cache is stored if it is changed
+    fn finish(&self, walk: Walk) -> (Bookshelf, IndexReport) {
+        let Walked {
+            mut books,
+            cache,
+            mut errors,
+            mut special,
+        } = walk.walked.into_inner().unwrap();
+
+        if let Some(dir) = &self.cache {
+            // NOTE: unchanged index isn't written again by every scan
+            if self.path.is_dir() && walk.old != Some(&cache) {
+                if let Err(e) = cache.store(dir) {
+                    warn!("Error for store index cache: {}", e);
+                }
+            }
+        }
+
+        books.sort_by(|a, b| a.get_path().cmp(b.get_path()));
+        errors.sort_by(|a, b| a.path.cmp(&b.path));
+        special.sort();
+        (
+            self.shelve(books.into_iter()),
+            IndexReport { errors, special },
+        )
+    }
+
+    fn shelve(&self, books: impl Iterator<Item = Book>) -> Bookshelf {
+        let books = books.fold(Bookshelf::from(self.path.clone()), |mut bs, book| {
+            bs.add(book);
+            bs
+        });
+        debug!("Indexed {} books of {}", books.len(), self.path.display());
+        books
+    }
+
+    /// Books of side, entries which can't be read are only logged
     pub fn index(&self) -> Bookshelf {
-        WalkDir::new(self.path.clone())
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            // NOTE: not index files begins with `.`
-            .filter(|e| !e.file_name().to_str().unwrap().starts_with("."))
-            .fold(Bookshelf::from(self.path.clone()), |mut bs, entry| {
-                bs.add(Book::from(entry.path().to_path_buf()));
-                bs
-            })
+        self.scan().0
     }
 
-    pub fn index_map(&self, map: fn(de: &DirEntry)) -> Bookshelf {
-        WalkDir::new(self.path.clone())
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            // NOTE: not index files begins with `.`
-            .filter(|e| !e.file_name().to_str().unwrap().starts_with("."))
-            .map(|e| {
-                map(&e);
-                e
-            })
-            .fold(Bookshelf::from(self.path.clone()), |mut bs, entry| {
-                bs.add(Book::from(entry.path().to_path_buf()));
-                bs
-            })
+    /// Books of side with errors of entries missing from index
+    ///
+    /// Directories are read and books are made in parallel, books are added
+    /// in order of paths so first of books with equal names wins
+    pub fn scan(&self) -> (Bookshelf, IndexReport) {
+        let old = self
+            .cache
+            .as_ref()
+            .map(|dir| IndexCache::load(dir, &self.path));
+        let walk = self.start(old.as_ref());
+
+        let ancestors = self.ancestors();
+        rayon::scope(|scope| self.walk(scope, self.path.clone(), ancestors, &walk));
+        self.finish(walk)
+    }
+
+    /// Books of side chosen by visitor, entries which can't be read are only
+    /// logged
+    pub fn index_with<F>(&self, visit: F) -> Bookshelf
+    where
+        F: FnMut(&mut Book) -> Visit,
+    {
+        self.scan_with(visit).0
+    }
+
+    /// Books of side chosen by visitor with errors of entries missing from
+    /// index, files are visited one by one in order of paths
+    ///
+    /// Visitor is closure, so it can keep state between calls: count files,
+    /// collect their details or report progress
+    pub fn scan_with<F>(&self, mut visit: F) -> (Bookshelf, IndexReport)
+    where
+        F: FnMut(&mut Book) -> Visit,
+    {
+        let old = self
+            .cache
+            .as_ref()
+            .map(|dir| IndexCache::load(dir, &self.path));
+        let walk = self.start(old.as_ref());
+
+        self.walk_with(self.path.clone(), &self.ancestors(), &walk, &mut visit);
+        self.finish(walk)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use super::*;
+    use crate::naming::{NamingRule, Scope};
 
     #[test]
     fn iterate() {
@@ -67,13 +722,270 @@ mod tests {
     }
 
     #[test]
-    fn iterate_with_map() {
+    fn parallel_index() {
+        let ixer = Indexer::new(PathBuf::from("tests/compare/local"));
+        let paths = |books: Bookshelf| {
+            books
+                .iter()
+                .map(|b| b.get_path().clone())
+                .collect::<Vec<PathBuf>>()
+        };
+
+        assert_eq!(paths(ixer.index()), paths(ixer.index_with(|_| Visit::Keep)));
+        assert!(
+            paths(ixer.index()).contains(&PathBuf::from("tests/compare/local/Fiction/moved.txt"))
+        );
+    }
+
+    #[test]
+    fn index_with_cache() {
+        let dir = PathBuf::from("tests/indexer_cache");
+        let root = PathBuf::from("tests/compare/local");
+        let ixer = Indexer::new(root.clone()).with_cache(dir.clone());
+        let paths = |books: &Bookshelf| {
+            books
+                .iter()
+                .map(|b| b.get_path().clone())
+                .collect::<Vec<PathBuf>>()
+        };
+
+        let scanned = ixer.index();
+        let mut hashes = BTreeMap::new();
+        hashes.insert(root.join("same.txt"), 42);
+        let remembered = IndexCache::remember(&dir, &root, &hashes);
+        let cached = ixer.index();
+        let visited = ixer.index_with(|_| Visit::Keep);
+        let stored = IndexCache::load(&dir, &root);
+
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert_eq!(remembered, Ok(()));
+        assert_eq!(paths(&cached), paths(&scanned));
+        assert_eq!(paths(&cached), paths(&Indexer::new(root.clone()).index()));
+        // Fixtures are older than mtime granularity, hash is kept by cache
+        let same = cached.get(&Book::new(String::from("same.txt"))).unwrap();
+        assert_eq!(same.get_hash(), Some(42));
+        let same = visited.get(&Book::new(String::from("same.txt"))).unwrap();
+        assert_eq!(same.get_hash(), Some(42));
+        assert!(stored.dir(Path::new("Fiction"), 0).is_none());
+        assert!(!stored.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_errors() {
+        let dir = PathBuf::from("tests/scan_errors");
+        let link = dir.join("broken.txt");
+        let created = fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(dir.join("book.txt"), "book"))
+            .and_then(|_| std::os::unix::fs::symlink("missing.txt", &link));
+        let (books, report) = Indexer::new(dir.clone()).scan();
+        let visited = Indexer::new(dir.clone()).scan_with(|_| Visit::Keep);
+
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert!(created.is_ok());
+        assert_eq!(books.len(), 1);
+        assert!(!report.is_complete());
+        assert_eq!(report.get_errors()[0].path, link);
+        assert_eq!(report.get_errors()[0].error_kind, "not_found");
+        assert_eq!(visited.0.len(), books.len());
+        assert_eq!(visited.1, report);
+
+        let (books, report) = Indexer::new(PathBuf::from("tests/missing")).scan();
+        assert!(books.is_empty());
+        assert_eq!(report.get_errors().len(), 1);
+        assert!(Indexer::new(PathBuf::from("tests/iterate"))
+            .scan()
+            .1
+            .is_complete());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = PathBuf::from("tests/invalid_names");
+        let invalid = dir.join(OsStr::from_bytes(b"book_\xff.txt"));
+        let created = fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(dir.join("book.txt"), "book"))
+            .and_then(|_| fs::write(&invalid, "invalid"));
+        let (books, report) = Indexer::new(dir.clone()).scan();
+
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert!(created.is_ok());
+        assert_eq!(books.len(), 1);
+        assert_eq!(report.get_errors().len(), 1);
+        assert_eq!(report.get_errors()[0].path, invalid);
+        assert_eq!(report.get_errors()[0].error_kind, "invalid_name");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_policy() {
+        use std::os::unix::{fs::symlink, net::UnixListener};
+
+        let dir = PathBuf::from("tests/links");
+        let side = dir.join("side");
+        let created = fs::create_dir_all(&side)
+            .and_then(|_| fs::create_dir_all(dir.join("target")))
+            .and_then(|_| fs::write(side.join("book.txt"), "book"))
+            .and_then(|_| fs::write(dir.join("target/outer.txt"), "outer"))
+            .and_then(|_| symlink("../target/outer.txt", side.join("linked.txt")))
+            .and_then(|_| symlink("../target", side.join("folder")))
+            .and_then(|_| symlink(".", side.join("loop")))
+            .and_then(|_| UnixListener::bind(side.join("socket")).map(|_| ()));
+        let scan = |links: Links| {
+            let (books, report) = Indexer::new(side.clone()).with_links(links).scan();
+            let names = books
+                .iter()
+                .map(|b| b.get_name().clone())
+                .collect::<Vec<String>>();
+            (names, report)
+        };
+        let ignored = scan(Links::Ignore);
+        let copied = scan(Links::Copy);
+        let followed = scan(Links::Follow);
+
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert!(created.is_ok());
+        assert_eq!(ignored.0, ["book.txt"]);
+        assert!(ignored.1.is_complete());
+        assert_eq!(ignored.1.get_special(), &vec![side.join("socket")]);
+        assert_eq!(copied, followed);
+        assert_eq!(followed.0, ["book.txt", "linked.txt", "outer.txt"]);
+        assert_eq!(followed.1.get_errors().len(), 1);
+        assert_eq!(followed.1.get_errors()[0].path, side.join("loop"));
+        assert_eq!(followed.1.get_errors()[0].error_kind, "link_loop");
+    }
+
+    #[test]
+    fn walk_options() {
+        let root = PathBuf::from("tests/walk_options");
+        let files = [
+            "visible.txt",
+            ".hidden.txt",
+            ".ebsync-state.yaml",
+            ".dir/inner.txt",
+            ".kobo/system.txt",
+            "LOST.DIR/lost.txt",
+            "sub/sub.txt",
+            "sub/deep/deeper.txt",
+        ];
+        let created = files.iter().try_for_each(|file| {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(path, "book"))
+        });
+        let scan = |options: WalkOptions| {
+            let ixer = Indexer::new(root.clone()).with_options(options);
+            let names = |books: Bookshelf| {
+                books
+                    .iter()
+                    .map(|b| b.get_name().clone())
+                    .collect::<Vec<String>>()
+            };
+            (names(ixer.index()), names(ixer.index_with(|_| Visit::Keep)))
+        };
+        let default = scan(WalkOptions::default());
+        let hidden = scan(WalkOptions {
+            hidden: true,
+            ..WalkOptions::default()
+        });
+        let all = scan(WalkOptions {
+            hidden: true,
+            devices: Vec::new(),
+            ..WalkOptions::default()
+        });
+        let shallow = scan(WalkOptions {
+            max_depth: Some(1),
+            ..WalkOptions::default()
+        });
+
+        if let Err(e) = fs::remove_dir_all(&root) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert!(created.is_ok());
+        assert_eq!(default.0, ["deeper.txt", "sub.txt", "visible.txt"]);
+        assert_eq!(
+            hidden.0,
+            [
+                ".hidden.txt",
+                "deeper.txt",
+                "inner.txt",
+                "sub.txt",
+                "visible.txt"
+            ]
+        );
+        // Files of sync are skipped anyway
+        assert_eq!(
+            all.0,
+            [
+                ".hidden.txt",
+                "deeper.txt",
+                "inner.txt",
+                "lost.txt",
+                "sub.txt",
+                "system.txt",
+                "visible.txt"
+            ]
+        );
+        assert_eq!(shallow.0, ["sub.txt", "visible.txt"]);
+        for (index, index_with) in [default, hidden, all, shallow].iter() {
+            assert_eq!(index, index_with);
+        }
+    }
+
+    #[test]
+    fn iterate_with_naming() {
+        let naming = Naming::new(&[NamingRule {
+            pattern: String::from(r"^file_(?P<title>\w+)$"),
+            scope: Scope::Name,
+        }])
+        .unwrap();
+        let ixer = Indexer::new(PathBuf::from("tests/iterate")).with_naming(naming);
+
+        let titles = ixer
+            .index()
+            .iter()
+            .map(|b| b.metadata().title)
+            .collect::<Vec<Option<String>>>();
+        assert_eq!(
+            titles,
+            [
+                Some(String::from("one")),
+                Some(String::from("three")),
+                Some(String::from("two")),
+            ]
+        );
+    }
+
+    #[test]
+    fn iterate_with_visitor() {
         let ixer = Indexer::new(PathBuf::from("tests/iterate"));
+        let mut visited = 0;
         let ixer_res: Vec<_> = ixer
-            .index_map(|de| assert_eq!(de.path().exists(), true))
+            .index_with(|book| {
+                assert!(book.get_path().exists());
+                visited += 1;
+                Visit::Keep
+            })
             .iter()
             .cloned()
             .collect();
+        assert_eq!(visited, 3);
         assert_eq!(
             ixer_res,
             [
@@ -83,4 +995,31 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn visitor_skips_and_stops() {
+        let ixer = Indexer::new(PathBuf::from("tests/iterate"));
+        let mut sizes = BTreeMap::new();
+        let books = ixer.index_with(|book| {
+            let path = book.get_path().clone();
+            sizes.insert(path.clone(), fs::metadata(&path).unwrap().len());
+            match path.file_name().unwrap().to_str().unwrap() {
+                "file_one.txt" => {
+                    *book = book.clone().with_metadata(Metadata {
+                        title: Some(String::from("One")),
+                        ..Metadata::default()
+                    });
+                    Visit::Keep
+                }
+                "file_three.txt" => Visit::Skip,
+                _ => Visit::Stop,
+            }
+        });
+
+        // Files are visited in order of names, walk stops at third
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(books.len(), 1);
+        let one = books.get(&Book::new(String::from("file_one.txt"))).unwrap();
+        assert_eq!(one.metadata().title, Some(String::from("One")));
+    }
 }
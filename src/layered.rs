@@ -0,0 +1,312 @@
+//! Layered entity
+//!
+//! Settings merged from config layers, every value remembers its origin
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use dirs::config_dir;
+use serde_json::{Map, Value};
+
+use crate::config::{leaves, parse_value, profiles_of, value_of, Profile};
+
+/// Per-library config file name, stored in root of source
+pub const LIBRARY_CONFIG: &str = ".ebsync.yaml";
+
+/// Prefix of environment variables overriding settings of all profiles
+pub const ENV_PREFIX: &str = "EBSYNC_";
+
+/// Profile settings which can be set by environment variables
+const ENV_KEYS: &[&str] = &[
+    "direction",
+    "delete",
+    "inbox",
+    "inbox_filing",
+    "trash_retention",
+    "max_change",
+    "concurrency",
+    "index_cache",
+    "require_complete_index",
+    "source_links",
+    "destination_links",
+    "hidden",
+    "max_depth",
+    "devices",
+];
+
+/// Config shared by all users of system
+pub fn system_config_path() -> PathBuf {
+    PathBuf::from("/etc")
+        .join(env!("CARGO_PKG_NAME"))
+        .join("config.yaml")
+}
+
+/// Config of current user
+pub fn user_config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("config.yaml"))
+}
+
+/// Layer which set value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    System(PathBuf),
+    User(PathBuf),
+    Library(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::System(path) => write!(f, "system config {}", path.display()),
+            Origin::User(path) => write!(f, "user config {}", path.display()),
+            Origin::Library(path) => write!(f, "library config {}", path.display()),
+            Origin::Env(name) => write!(f, "environment {}", name),
+            Origin::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Value of profile settings for every profile
+fn for_profiles(names: &[String], settings: Map<String, Value>) -> Value {
+    let profiles = names
+        .iter()
+        .map(|name| (name.clone(), Value::Object(settings.clone())))
+        .collect::<Map<String, Value>>();
+
+    let mut layer = Map::new();
+    layer.insert(String::from("profiles"), Value::Object(profiles));
+    Value::Object(layer)
+}
+
+/// Settings merged layer by layer, later layers override values of earlier
+#[derive(Debug, Clone)]
+pub struct Layered {
+    value: Value,
+    origins: BTreeMap<String, Origin>,
+}
+
+impl Default for Layered {
+    fn default() -> Self {
+        Layered::new()
+    }
+}
+
+impl Layered {
+    pub fn new() -> Self {
+        Layered {
+            value: Value::Object(Map::new()),
+            origins: BTreeMap::new(),
+        }
+    }
+
+    pub fn get_value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Merge layer of config layout, maps are merged key by key and other
+    /// values (arrays too) are replaced
+    pub fn merge(&mut self, layer: Value, origin: Origin) {
+        let set = leaves(&layer);
+
+        Layered::merge_value(&mut self.value, layer);
+
+        for (path, value) in set {
+            // Empty map is merged into existing one
+            if value.is_object() {
+                continue;
+            }
+
+            // Value replacing map hides origins of map values
+            let prefix = format!("{}.", path);
+            self.origins.retain(|p, _| !p.starts_with(&prefix));
+            self.origins.insert(path, origin.clone());
+        }
+    }
+
+    fn merge_value(base: &mut Value, layer: Value) {
+        match (base, layer) {
+            (Value::Object(base), Value::Object(layer)) => {
+                for (key, value) in layer {
+                    match base.get_mut(&key) {
+                        Some(base) if base.is_object() && value.is_object() => {
+                            Layered::merge_value(base, value)
+                        }
+                        _ => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, layer) => *base = layer,
+        }
+    }
+
+    /// Names of profiles merged so far
+    pub fn profile_names(&self) -> Vec<String> {
+        self.value
+            .get("profiles")
+            .and_then(|p| p.as_object())
+            .map(|p| p.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Source of profile merged so far
+    pub fn source_of(&self, name: &str) -> Option<PathBuf> {
+        self.value
+            .get("profiles")?
+            .get(name)?
+            .get("source")?
+            .as_str()
+            .map(PathBuf::from)
+    }
+
+    /// Merge settings of per-library config into profile
+    pub fn merge_profile(&mut self, name: &str, settings: Value, origin: Origin) {
+        if let Value::Object(settings) = settings {
+            self.merge(for_profiles(&[name.to_string()], settings), origin);
+        }
+    }
+
+    /// Merge `EBSYNC_*` variables into every profile, values are parsed as
+    /// YAML scalars, variables of unknown settings are skipped
+    pub fn merge_env<I>(&mut self, vars: I)
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let names = self.profile_names();
+
+        for (name, value) in vars {
+            let key = match name.strip_prefix(ENV_PREFIX) {
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            if !ENV_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+
+            let value = parse_value(&value);
+            let mut settings = Map::new();
+            settings.insert(key, value);
+
+            self.merge(for_profiles(&names, settings), Origin::Env(name));
+        }
+    }
+
+    /// Merge profile settings into every profile
+    pub fn merge_all(&mut self, settings: Map<String, Value>, origin: Origin) {
+        if !settings.is_empty() {
+            let names = self.profile_names();
+            self.merge(for_profiles(&names, settings), origin);
+        }
+    }
+
+    pub fn profiles(&self) -> Result<BTreeMap<String, Profile>, String> {
+        profiles_of(self.value.clone())
+    }
+
+    /// Effective settings with origins, settings not set by any layer have
+    /// default origin
+    pub fn effective(&self) -> Result<Vec<(String, Value, Origin)>, String> {
+        Ok(leaves(&value_of(self.profiles()?)?)
+            .into_iter()
+            .map(|(path, value)| {
+                let origin = self.origins.get(&path).cloned().unwrap_or(Origin::Default);
+                (path, value, origin)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn layered() -> Layered {
+        let mut layered = Layered::new();
+        layered.merge(
+            json!({"profiles": {"kobo": {
+                "source": "/system/library",
+                "destination": "/media/kobo",
+                "max_change": 20,
+                "formats": ["epub"],
+            }}}),
+            Origin::System(PathBuf::from("/etc/config.yaml")),
+        );
+        layered.merge(
+            json!({"profiles": {"kobo": {
+                "source": "/user/library",
+                "formats": ["fb2"],
+            }}}),
+            Origin::User(PathBuf::from("/home/config.yaml")),
+        );
+        layered
+    }
+
+    #[test]
+    fn merge() {
+        let layered = layered();
+
+        let kobo = layered.profiles().unwrap().remove("kobo").unwrap();
+        assert_eq!(kobo.source, PathBuf::from("/user/library"));
+        assert_eq!(kobo.destination, Some(PathBuf::from("/media/kobo")));
+        assert_eq!(kobo.max_change, 20);
+        assert_eq!(kobo.selection.formats, [String::from("fb2")]);
+        assert_eq!(
+            layered.source_of("kobo"),
+            Some(PathBuf::from("/user/library"))
+        );
+    }
+
+    #[test]
+    fn env_and_cli() {
+        let mut layered = layered();
+        layered.merge_profile(
+            "kobo",
+            json!({"delete": true, "max_change": 30}),
+            Origin::Library(PathBuf::from("/user/library/.ebsync.yaml")),
+        );
+        layered.merge_env(vec![
+            (String::from("EBSYNC_MAX_CHANGE"), String::from("40")),
+            (String::from("EBSYNC_DIRECTION"), String::from("push")),
+            (String::from("EBSYNC_UNKNOWN"), String::from("1")),
+            (String::from("HOME"), String::from("/home")),
+        ]);
+
+        let mut cli = Map::new();
+        cli.insert(String::from("direction"), json!("pull"));
+        layered.merge_all(cli, Origin::Cli);
+
+        let effective = layered
+            .effective()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _, origin)| (path, origin))
+            .collect::<BTreeMap<String, Origin>>();
+
+        assert_eq!(
+            effective["profiles.kobo.delete"],
+            Origin::Library(PathBuf::from("/user/library/.ebsync.yaml"))
+        );
+        assert_eq!(
+            effective["profiles.kobo.max_change"],
+            Origin::Env(String::from("EBSYNC_MAX_CHANGE"))
+        );
+        assert_eq!(effective["profiles.kobo.direction"], Origin::Cli);
+        assert_eq!(
+            effective["profiles.kobo.destination"],
+            Origin::System(PathBuf::from("/etc/config.yaml"))
+        );
+        assert_eq!(effective["profiles.kobo.trash_retention"], Origin::Default);
+
+        let kobo = layered.profiles().unwrap().remove("kobo").unwrap();
+        assert_eq!(kobo.max_change, 40);
+        assert!(kobo.delete);
+    }
+}
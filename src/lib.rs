@@ -4,8 +4,25 @@
 
 pub mod book;
 pub mod bookshelf;
+pub mod cache;
+pub mod compare;
 pub mod config;
+pub mod device;
+pub mod direction;
+pub mod filter;
+pub mod guard;
+pub mod inbox;
 pub mod indexer;
+pub mod layered;
+pub mod logger;
+pub mod metadata;
+pub mod naming;
 pub mod opt;
+pub mod output;
+pub mod progress;
+pub mod report;
+pub mod state;
+pub mod template;
+pub mod trash;
 pub mod updater;
 pub mod utility;
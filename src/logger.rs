@@ -0,0 +1,251 @@
+//! Logger entity
+//!
+//! Backend of `log` facade: console filtered by verbosity and optional log
+//! file rotated by size
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Local;
+use indicatif::ProgressBar;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::utility::create_dir_for_path;
+
+/// Size of log file to rotate it
+pub const LOG_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Number of rotated log files kept beside current one
+pub const LOG_FILE_COUNT: usize = 3;
+
+/// Bar drawn to console while transfers run, messages are printed above it
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Print console messages above bar, `None` prints them plainly again
+pub fn draw_bar(bar: Option<ProgressBar>) {
+    if let Ok(mut drawn) = BAR.lock() {
+        *drawn = bar;
+    }
+}
+
+/// Print line to stderr, bar is hidden while it is printed
+fn print(line: &str) {
+    // NOTE: bar is cloned out to not hold lock while it is redrawn
+    match BAR.lock().ok().and_then(|bar| bar.clone()) {
+        Some(bar) => bar.suspend(|| eprintln!("{}", line)),
+        None => eprintln!("{}", line),
+    }
+}
+
+/// Console level: info by default, `-v` debug, `-vv` trace, `-q` warnings,
+/// `-qq` errors, `-qqq` nothing
+pub fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    let levels = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+    let index = (3 + i32::from(verbose) - i32::from(quiet)).clamp(0, 5);
+    levels[index as usize]
+}
+
+fn timestamp() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+/// Log file `name` with rotated copies `name.1` (newest) .. `name.N`
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    count: usize,
+}
+
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+impl LogFile {
+    fn open(path: PathBuf, max_size: u64, count: usize) -> io::Result<Self> {
+        create_dir_for_path(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(LogFile {
+            path,
+            file,
+            size,
+            max_size,
+            count,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..self.count).rev() {
+            let older = rotated(&self.path, index);
+            if older.exists() {
+                fs::rename(&older, rotated(&self.path, index + 1))?;
+            }
+        }
+        if self.count > 0 {
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+pub struct Logger {
+    console: LevelFilter,
+    file: Option<Mutex<LogFile>>,
+}
+
+impl Logger {
+    pub fn new(console: LevelFilter) -> Self {
+        Logger {
+            console,
+            file: None,
+        }
+    }
+
+    /// Write log to file too, file gets debug messages at least
+    pub fn with_file(mut self, path: PathBuf) -> Result<Self, String> {
+        let file = LogFile::open(path.clone(), LOG_FILE_SIZE, LOG_FILE_COUNT).map_err(|e| {
+            format!(
+                "fail to open log file: {} with error: {}",
+                path.display(),
+                e
+            )
+        })?;
+        self.file = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    fn file_level(&self) -> LevelFilter {
+        match self.file {
+            Some(_) => self.console.max(LevelFilter::Debug),
+            None => LevelFilter::Off,
+        }
+    }
+
+    /// Install logger as backend of `log` macros
+    pub fn init(self) -> Result<(), String> {
+        log::set_max_level(self.console.max(self.file_level()));
+        log::set_boxed_logger(Box::new(self))
+            .map_err(|e| format!("fail to init logger with error: {}", e))
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.console || metadata.level() <= self.file_level()
+    }
+
+    fn log(&self, record: &Record) {
+        let line = || {
+            format!(
+                "{} {:5} {}: {}",
+                timestamp(),
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        };
+
+        if record.level() <= self.console {
+            // Messages are plain unless details are asked by verbosity
+            if self.console >= Level::Debug {
+                print(&line());
+            } else {
+                print(&record.args().to_string());
+            }
+        }
+
+        if let Some(file) = &self.file {
+            if record.level() <= self.file_level() {
+                if let Ok(mut file) = file.lock() {
+                    if let Err(e) = file.write(&line()) {
+                        eprintln!("fail to write log file with error: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(level(0, 0), LevelFilter::Info);
+        assert_eq!(level(2, 0), LevelFilter::Trace);
+        assert_eq!(level(5, 0), LevelFilter::Trace);
+        assert_eq!(level(0, 1), LevelFilter::Warn);
+        assert_eq!(level(0, 5), LevelFilter::Off);
+    }
+
+    #[test]
+    fn rotate_log_file() {
+        let dir = PathBuf::from("tests/logger");
+        let path = dir.join("ebsync.log");
+
+        let mut file = LogFile::open(path.clone(), 10, 2).unwrap();
+        for line in ["first", "second", "third", "fourth"].iter() {
+            file.write(line).unwrap();
+        }
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        let logs = (
+            read(path.clone()),
+            read(rotated(&path, 1)),
+            read(rotated(&path, 2)),
+            rotated(&path, 3).exists(),
+        );
+
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            panic!("Remove error: {}", e);
+        }
+
+        assert_eq!(
+            logs,
+            (
+                String::from("fourth\n"),
+                String::from("third\n"),
+                String::from("second\n"),
+                false
+            )
+        );
+    }
+}
@@ -1,150 +1,1218 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
 
-use dirs::config_dir;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{error, info, warn, LevelFilter};
+use serde_json::{json, Map, Value};
 
 use structopt::StructOpt;
 
-use e_book_sync_library::config::Config;
-use e_book_sync_library::opt::Opt;
-use e_book_sync_library::updater::{Update, Updater};
+use e_book_sync_library::book::Book;
+use e_book_sync_library::bookshelf::Bookshelf;
+use e_book_sync_library::cache::user_cache_path;
+use e_book_sync_library::compare::Difference;
+use e_book_sync_library::config::{
+    parse_value, Config, Destination, Profile, CONFIG_VERSION, DEFAULT_PROFILE,
+};
+use e_book_sync_library::direction::Directions;
+use e_book_sync_library::guard::Guard;
+use e_book_sync_library::indexer::{IndexReport, Indexer};
+use e_book_sync_library::layered::{
+    system_config_path, user_config_path, Layered, Origin, LIBRARY_CONFIG,
+};
+use e_book_sync_library::logger::{self, Logger};
+use e_book_sync_library::opt::{Command, ConfigCommand, Opt, Side, TrashCommand};
+use e_book_sync_library::output::{Output, Record};
+use e_book_sync_library::progress::{Observer, Progress};
+use e_book_sync_library::report::{Outcome, SyncReport};
+use e_book_sync_library::trash::Trash;
+use e_book_sync_library::updater::{BookStatus, Update, Updater};
 use e_book_sync_library::utility::create_dir_for_path;
 
+fn print_status(
+    out: &Output,
+    update: Update,
+    book_status: &BookStatus,
+    planned: bool,
+    (src_root, dst_root): (&Path, &Path),
+) {
+    let line = format!(
+        "{} {} {} from: {} to: {}",
+        book_status.get_name(),
+        book_status.get_status(),
+        arrow(&update),
+        book_status
+            .get_src()
+            .strip_prefix(src_root)
+            .unwrap_or_else(|_| book_status.get_src())
+            .to_str()
+            .unwrap(),
+        book_status
+            .get_dst()
+            .strip_prefix(dst_root)
+            .unwrap_or_else(|_| book_status.get_dst())
+            .to_str()
+            .unwrap()
+    );
+
+    out.record(Record::transfer(update, book_status, planned), &line);
+}
+
+fn arrow(update: &Update) -> &'static str {
+    match update {
+        Update::OnlyFromLocal => "+>",
+        Update::OnlyFromLocalSync => "=>",
+        Update::OnlyFromLocalMirror => "=x",
+        Update::OnlyFromForeign => "<+",
+        Update::OnlyFromForeignSync => "<=",
+        Update::Bidirectional => "<>",
+        Update::Deletion => "x>",
+    }
+}
+
+/// Progress bar of transfers with throughput and ETA, drawn to stderr
+#[derive(Default)]
+struct Bar(Mutex<Option<ProgressBar>>);
+
+impl Observer for Bar {
+    fn started(&self, progress: &Progress) {
+        // Moves and deletions are instant, only copies get bar
+        if progress.get_bytes_total() == 0 {
+            return;
+        }
+
+        let bar = ProgressBar::new(progress.get_bytes_total()).with_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta} {msg}",
+            )
+            .unwrap()
+            .progress_chars("=> "),
+        );
+        logger::draw_bar(Some(bar.clone()));
+        self.0.lock().unwrap().replace(bar);
+    }
+
+    fn progressed(&self, progress: &Progress) {
+        if let Some(bar) = self.0.lock().unwrap().as_ref() {
+            bar.set_position(progress.get_bytes_done());
+            bar.set_message(format!(
+                "{}/{} {}",
+                progress.get_files_done(),
+                progress.get_files_total(),
+                progress
+                    .get_current()
+                    .and_then(|p| p.file_name())
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            ));
+        }
+    }
+
+    fn finished(&self, _progress: &Progress) {
+        if let Some(bar) = self.0.lock().unwrap().take() {
+            logger::draw_bar(None);
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Updates of profile in order they should run
+fn updates(profile: &Profile, directions: &Directions) -> Vec<Update> {
+    let mut updates = Vec::new();
+    if profile.delete {
+        updates.push(Update::Deletion);
+    }
+    updates.append(&mut directions.updates());
+    updates
+}
+
+fn sync_destination(
+    out: &Output,
+    profile: &Profile,
+    directions: &Directions,
+    updater: &Updater,
+    (confirm, confirm_empty): (bool, bool),
+    dry_run: bool,
+) -> SyncReport {
+    let source = updater.get_local();
+    let destination = updater.get_foreign();
+
+    info!(
+        "Sync: local::{} <-> device::{}",
+        source.to_str().unwrap(),
+        destination.to_str().unwrap()
+    );
+
+    // NOTE: guard of destination limits changes of all its updates together
+    let guard = Guard::new(profile.max_change, confirm)
+        .with_empty_confirmed(confirm_empty)
+        .with_complete_index(profile.require_complete_index);
+
+    if let Err(e) = guard.check_paths(source, destination) {
+        error!("Refuse to sync: {}", e);
+        let mut report = SyncReport::new();
+        report.abort(e);
+        return report;
+    }
+
+    let mut report = SyncReport::new();
+
+    for update in updates(profile, directions) {
+        let deletion = matches!(update, Update::Deletion);
+        let (src_root, dst_root) = match update {
+            Update::OnlyFromLocal | Update::Bidirectional => (source, destination),
+            Update::OnlyFromLocalSync | Update::OnlyFromLocalMirror => (destination, destination),
+            Update::OnlyFromForeign => (destination, source),
+            Update::OnlyFromForeignSync | Update::Deletion => (source, source),
+        };
+
+        // Dry run plans every update against current sides, checked by same guard
+        let results = if dry_run {
+            updater.plan_guarded(update, &guard)
+        } else {
+            updater.update_guarded(update, &guard)
+        };
+
+        match results {
+            Ok(results) => {
+                for book_status in results {
+                    report.add(&book_status);
+
+                    // Deletion trashes books on both sides, each inside own root
+                    let roots = if deletion && book_status.get_src().starts_with(destination) {
+                        (destination.as_path(), destination.as_path())
+                    } else {
+                        (src_root.as_path(), dst_root.as_path())
+                    };
+                    print_status(out, update, &book_status, dry_run, roots);
+                }
+            }
+            Err(e) => {
+                error!("Sync aborted by safety guard: {}", e);
+                warn!(
+                    "Check sync paths and unreadable entries, or use --confirm or --confirm-empty"
+                );
+                report.index(updater.foreign_report());
+                report.abort(e);
+                return report;
+            }
+        }
+    }
+
+    report.index(updater.foreign_report());
+
+    if dry_run {
+        info!(
+            "Planned for device::{}: {} transfers",
+            destination.to_str().unwrap(),
+            report.get_transferred()
+        );
+        report.finish();
+        return report;
+    }
+
+    if let Err(e) = updater.store_state() {
+        error!("Error for store sync state: {}", e);
+        report.error(format!("fail to store sync state with error: {}", e));
+    }
+
+    let retention = Duration::from_secs(profile.trash_retention * 24 * 60 * 60);
+    for root in [source, destination].iter() {
+        match Trash::new(root.to_path_buf()).empty(Some(retention)) {
+            Ok(batches) => {
+                for batch in batches {
+                    info!("Trash batch {} expired and removed", batch);
+                }
+            }
+            Err(e) => error!("Error for empty trash: {}", e),
+        }
+    }
+
+    info!(
+        "Synced device::{}: {} transferred, {} failed",
+        destination.to_str().unwrap(),
+        report.get_transferred(),
+        report.get_failed()
+    );
+
+    report.finish();
+    report
+}
+
+/// Updater of profile for destination
+fn updater(profile: &Profile, destination: Destination) -> Result<Updater, String> {
+    let filter = profile
+        .filter(&destination)
+        .map_err(|e| format!("Error for parse selection rules: {}", e))?;
+    let directions = profile
+        .directions()
+        .map_err(|e| format!("Error for parse direction rules: {}", e))?;
+    let naming = profile
+        .naming()
+        .map_err(|e| format!("Error for parse naming rules: {}", e))?;
+    let template = destination
+        .template()
+        .map_err(|e| format!("Error for parse template: {}", e))?;
+
+    let mut updater = Updater::new(profile.source.clone(), destination.path)
+        .with_filter(filter)
+        .with_directions(directions)
+        .with_naming(naming)
+        .with_links(profile.source_links, profile.destination_links)
+        .with_walk_options(profile.walk_options())
+        .with_concurrency(profile.concurrency);
+    if let Some(inbox) = profile.inbox() {
+        updater = updater.with_inbox(inbox);
+    }
+    if let Some(template) = template {
+        updater = updater.with_template(template);
+    }
+    if let Some(dir) = index_cache(profile) {
+        updater = updater.with_cache(dir);
+    }
+
+    Ok(updater)
+}
+
+/// Dir of index caches if profile keeps them
+fn index_cache(profile: &Profile) -> Option<PathBuf> {
+    if profile.index_cache {
+        user_cache_path()
+    } else {
+        None
+    }
+}
+
+/// Report of command refused by wrong settings
+fn config_error(error: String) -> SyncReport {
+    let mut report = SyncReport::new();
+    report.config_error(error);
+    report
+}
+
+/// Run command for every destination of profile, source is indexed once
+fn for_destinations<F>(profile: &Profile, progress: bool, mut run: F) -> SyncReport
+where
+    F: FnMut(&Updater) -> SyncReport,
+{
+    let source = &profile.source;
+    let mut report = SyncReport::new();
+
+    if !source.exists() {
+        let e = format!("Source path: {} doesn't exist", source.to_str().unwrap());
+        error!("{}", e);
+        report.error(e);
+        return report;
+    }
+
+    let naming = match profile.naming() {
+        Ok(naming) => naming,
+        Err(e) => {
+            error!("Error for parse naming rules: {}", e);
+            report.config_error(e);
+            return report;
+        }
+    };
+
+    let mut indexer = Indexer::new(source.clone())
+        .with_naming(naming)
+        .with_links(profile.source_links)
+        .with_options(profile.walk_options());
+    if let Some(dir) = index_cache(profile) {
+        indexer = indexer.with_cache(dir);
+    }
+    let (mut index, index_report) = indexer.scan();
+    // NOTE: errors of source are reported once, not by every destination
+    report.index(index_report.clone());
+
+    for destination in profile.destinations() {
+        if !destination.path.exists() {
+            let e = format!(
+                "Destination path: {} doesn't exist",
+                destination.path.to_str().unwrap()
+            );
+            error!("{}", e);
+            report.error(e);
+            continue;
+        }
+
+        let updater = match updater(profile, destination) {
+            Ok(updater) if progress => updater
+                .with_local_index(index)
+                .with_local_report(index_report.clone())
+                .with_observer(Box::new(Bar::default())),
+            Ok(updater) => updater
+                .with_local_index(index)
+                .with_local_report(index_report.clone()),
+            Err(e) => {
+                error!("{}", e);
+                report.config_error(e);
+                continue;
+            }
+        };
+
+        report.merge(run(&updater));
+
+        index = updater.into_local_index().unwrap();
+    }
+
+    report
+}
+
+/// Sync source with every destination of profile
+fn sync(
+    out: &Output,
+    profile: &Profile,
+    confirm: (bool, bool),
+    dry_run: bool,
+    progress: bool,
+) -> SyncReport {
+    let directions = match profile.directions() {
+        Ok(directions) => directions,
+        Err(e) => {
+            error!("Error for parse direction rules: {}", e);
+            return config_error(e);
+        }
+    };
+
+    for_destinations(profile, progress && !dry_run, |updater| {
+        sync_destination(out, profile, &directions, updater, confirm, dry_run)
+    })
+}
+
+/// Print number of differing books and pending transfers of every destination
+fn status(out: &Output, profile: &Profile) -> SyncReport {
+    let directions = match profile.directions() {
+        Ok(directions) => directions,
+        Err(e) => {
+            error!("Error for parse direction rules: {}", e);
+            return config_error(e);
+        }
+    };
+
+    for_destinations(profile, false, |updater| {
+        info!(
+            "Status: local::{} <-> device::{}",
+            updater.get_local().to_str().unwrap(),
+            updater.get_foreign().to_str().unwrap()
+        );
+
+        let mut differences = BTreeMap::new();
+        for comparison in updater.compare(false) {
+            *differences
+                .entry(comparison.get_difference().name().to_string())
+                .or_insert(0) += 1;
+        }
+        let count = |difference: Difference| differences.get(difference.name()).unwrap_or(&0);
+        let books = format!(
+            "Books: {} same, {} moved, {} changed, {} only on source, {} only on destination",
+            count(Difference::Same),
+            count(Difference::Moved),
+            count(Difference::Changed),
+            count(Difference::OnlyLocal),
+            count(Difference::OnlyForeign)
+        );
+
+        let pending = updates(profile, &directions)
+            .into_iter()
+            .map(|update| (update, updater.plan(update).len()))
+            .filter(|(_, count)| *count > 0)
+            .collect::<BTreeMap<Update, usize>>();
+        let pending_line = if pending.is_empty() {
+            String::from("Pending: nothing")
+        } else {
+            format!(
+                "Pending: {}",
+                pending
+                    .iter()
+                    .map(|(update, count)| format!("{} {}", count, arrow(update)))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        };
+
+        out.record(
+            Record::Status {
+                source: updater.get_local().clone(),
+                destination: updater.get_foreign().clone(),
+                differences: differences.clone(),
+                pending,
+            },
+            &format!("{}\n{}", books, pending_line),
+        );
+
+        SyncReport::synced()
+    })
+}
+
+fn path_of(path: Option<&PathBuf>) -> &str {
+    path.and_then(|p| p.to_str()).unwrap_or("")
+}
+
+/// Print book by book comparison of source with every destination
+fn diff(out: &Output, profile: &Profile, all: bool) -> SyncReport {
+    for_destinations(profile, false, |updater| {
+        info!(
+            "Diff: local::{} <-> device::{}",
+            updater.get_local().to_str().unwrap(),
+            updater.get_foreign().to_str().unwrap()
+        );
+
+        for comparison in updater.compare(false) {
+            let local = path_of(comparison.get_local());
+            let foreign = path_of(comparison.get_foreign());
+
+            let line = match comparison.get_difference() {
+                Difference::Same if all => format!("= {}", local),
+                Difference::Same => continue,
+                Difference::Moved => format!("~ {} -> {}", local, foreign),
+                Difference::Changed => format!("! {}", local),
+                Difference::OnlyLocal => format!("> {}", local),
+                Difference::OnlyForeign => format!("< {}", foreign),
+                Difference::Unreadable(e) => format!("? {}: {}", comparison.get_name(), e),
+            };
+
+            out.record(
+                Record::comparison(
+                    updater.get_local().clone(),
+                    updater.get_foreign().clone(),
+                    &comparison,
+                ),
+                &line,
+            );
+        }
+
+        SyncReport::synced()
+    })
+}
+
+fn print_books(out: &Output, books: &Bookshelf) {
+    let mut sorted = books.iter().collect::<Vec<&Book>>();
+    sorted.sort_by_key(|b| books.relative_path(b));
+
+    for book in sorted {
+        out.record(
+            Record::book(books, book),
+            books.relative_path(book).to_str().unwrap(),
+        );
+    }
+}
+
+/// List books of profile side, selected by profile or destination rules
+fn ls(out: &Output, profile: &Profile, side: Side) -> SyncReport {
+    match side {
+        Side::Source => {
+            if !profile.source.exists() {
+                let e = format!(
+                    "Source path: {} doesn't exist",
+                    profile.source.to_str().unwrap()
+                );
+                error!("{}", e);
+                let mut report = SyncReport::new();
+                report.error(e);
+                return report;
+            }
+
+            let (naming, filter) = match (profile.naming(), profile.selection.filter()) {
+                (Ok(naming), Ok(filter)) => (naming, filter),
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Error for parse config: {}", e);
+                    return config_error(e);
+                }
+            };
+
+            info!("Source: {}", profile.source.to_str().unwrap());
+            let mut indexer = Indexer::new(profile.source.clone())
+                .with_naming(naming)
+                .with_links(profile.source_links)
+                .with_options(profile.walk_options());
+            if let Some(dir) = index_cache(profile) {
+                indexer = indexer.with_cache(dir);
+            }
+            let (books, index_report) = indexer.scan();
+            print_books(out, &filter.apply(books));
+
+            let mut report = SyncReport::synced();
+            warn_incomplete(&profile.source, &index_report);
+            report.index(index_report);
+            report
+        }
+        Side::Destination => for_destinations(profile, false, |updater| {
+            info!("Destination: {}", updater.get_foreign().to_str().unwrap());
+            print_books(out, &updater.scan_area().1);
+
+            let mut report = SyncReport::synced();
+            warn_incomplete(updater.get_foreign(), &updater.foreign_report());
+            report.index(updater.foreign_report());
+            report
+        }),
+    }
+}
+
+/// Warn that books of unreadable entries are missing from listing
+fn warn_incomplete(root: &Path, report: &IndexReport) {
+    if !report.is_complete() {
+        warn!(
+            "{} entries of {} can't be read, their books aren't listed",
+            report.get_errors().len(),
+            root.display()
+        );
+    }
+}
+
+/// Check content of books present on both sides of every destination
+fn verify(out: &Output, profile: &Profile) -> SyncReport {
+    for_destinations(profile, false, |updater| {
+        let (mut equal, mut problems) = (0, 0);
+
+        for comparison in updater.compare(true) {
+            let line = match comparison.get_difference() {
+                Difference::Same | Difference::Moved => {
+                    equal += 1;
+                    continue;
+                }
+                Difference::OnlyLocal | Difference::OnlyForeign => continue,
+                Difference::Changed => format!(
+                    "{} content differs: {} and {}",
+                    comparison.get_name(),
+                    path_of(comparison.get_local()),
+                    path_of(comparison.get_foreign())
+                ),
+                Difference::Unreadable(e) => {
+                    format!("{} can't be read: {}", comparison.get_name(), e)
+                }
+            };
+
+            problems += 1;
+            out.record(
+                Record::comparison(
+                    updater.get_local().clone(),
+                    updater.get_foreign().clone(),
+                    &comparison,
+                ),
+                &line,
+            );
+        }
+
+        info!(
+            "Verified device::{}: {} equal, {} problems",
+            updater.get_foreign().to_str().unwrap(),
+            equal,
+            problems
+        );
+
+        let mut report = SyncReport::synced();
+        if problems > 0 {
+            report.error(format!(
+                "{} problems on: {}",
+                problems,
+                updater.get_foreign().to_str().unwrap()
+            ));
+        }
+        report
+    })
+}
+
+/// Print metadata extracted by naming rules of profile from books of paths
+fn preview(out: &Output, profile: &Profile, paths: &[PathBuf]) -> SyncReport {
+    let naming = match profile.naming() {
+        Ok(naming) => naming,
+        Err(e) => {
+            error!("Error for parse naming rules: {}", e);
+            return config_error(e);
+        }
+    };
+    if naming.is_empty() {
+        info!("No naming rules");
+        return SyncReport::synced();
+    }
+
+    let paths = if paths.is_empty() {
+        vec![profile.source.clone()]
+    } else {
+        paths.to_vec()
+    };
+
+    for path in paths {
+        let books = if path.is_dir() {
+            Indexer::new(path.clone()).index()
+        } else {
+            let mut books = Bookshelf::from(path.parent().unwrap_or(&path).to_path_buf());
+            books.add(Book::from(path.clone()));
+            books
+        };
+
+        for book in books.iter() {
+            // Rules match path relative to source, books out of source by
+            // path relative to previewed folder
+            let relative = book
+                .get_path()
+                .strip_prefix(&profile.source)
+                .unwrap_or_else(|_| books.relative_path(book));
+            let preview = naming.preview(relative);
+
+            let mut line = relative.to_str().unwrap().to_string();
+            for (rule, metadata) in preview.iter() {
+                let fields = match metadata {
+                    Some(metadata) => metadata
+                        .fields()
+                        .iter()
+                        .map(|(name, value)| format!("{}={}", name, value))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    None => String::from("no match"),
+                };
+                line.push_str(&format!("\n    {}: {}", rule.pattern, fields));
+            }
+            out.record(Record::preview(relative, &preview), &line);
+        }
+    }
+
+    SyncReport::synced()
+}
+
+/// Roots of side of profile, every destination for destination side
+fn roots(profile: &Profile, side: Side) -> Vec<PathBuf> {
+    match side {
+        Side::Source => vec![profile.source.clone()],
+        Side::Destination => profile
+            .destinations()
+            .into_iter()
+            .map(|destination| destination.path)
+            .collect(),
+    }
+}
+
+/// List, restore or remove batches of trash on side of profile
+fn trash(out: &Output, profile: &Profile, command: &TrashCommand) -> SyncReport {
+    let side = match command {
+        TrashCommand::List { side }
+        | TrashCommand::Restore { side, .. }
+        | TrashCommand::Empty { side, .. } => *side,
+    };
+    let mut report = SyncReport::new();
+
+    for root in roots(profile, side) {
+        if !root.exists() {
+            let e = format!("Path: {} doesn't exist", root.to_str().unwrap());
+            error!("{}", e);
+            report.error(e);
+            continue;
+        }
+
+        let trash = Trash::new(root.clone());
+        match command {
+            TrashCommand::List { .. } => match trash.batches() {
+                Ok(batches) => {
+                    for batch in batches {
+                        let books = trash.books(&batch);
+                        let line = books.iter().fold(
+                            format!(
+                                "{} {}: {} books",
+                                root.to_str().unwrap(),
+                                batch,
+                                books.len()
+                            ),
+                            |line, book| format!("{}\n    {}", line, book.to_str().unwrap()),
+                        );
+                        out.record(
+                            Record::TrashBatch {
+                                root: root.clone(),
+                                batch,
+                                books,
+                            },
+                            &line,
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!("Error for list trash: {}", e);
+                    report.error(format!("fail to list trash with error: {}", e));
+                    continue;
+                }
+            },
+            TrashCommand::Restore { batch, .. } => {
+                // NOTE: batch is on side of deletion, other roots of side
+                // are skipped
+                if !trash.get_path().join(batch).is_dir() {
+                    continue;
+                }
+
+                for book_status in trash.restore(batch) {
+                    report.add(&book_status);
+                    let line = format!(
+                        "{} {} to: {}",
+                        book_status.get_name(),
+                        book_status.get_status(),
+                        book_status.get_dst().to_str().unwrap()
+                    );
+                    out.record(Record::restore(root.clone(), batch, &book_status), &line);
+                }
+            }
+            TrashCommand::Empty { expired, .. } => {
+                let retention = if *expired {
+                    Some(Duration::from_secs(profile.trash_retention * 24 * 60 * 60))
+                } else {
+                    None
+                };
+
+                match trash.empty(retention) {
+                    Ok(batches) => {
+                        for batch in batches {
+                            info!("Trash batch {} removed", batch);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Error for empty trash: {}", e);
+                        report.error(format!("fail to empty trash with error: {}", e));
+                        continue;
+                    }
+                }
+            }
+        }
+
+        report.finish();
+    }
+
+    if let TrashCommand::Restore { batch, .. } = command {
+        if report.get_transferred() + report.get_failed() == 0 {
+            let e = format!("Trash batch: {} doesn't exist", batch);
+            error!("{}", e);
+            report.error(e);
+        }
+    }
+
+    report
+}
+
+/// Profiles layer set by source/destination of command line
+fn cli_profile(name: &str, profile: &Profile) -> Value {
+    json!({ "profiles": { name: {
+        "source": profile.source,
+        "destination": profile.destination,
+        "destinations": profile.destinations,
+    }}})
+}
+
+/// Settings of command line overriding settings of every profile
+fn cli_settings(opt: &Opt) -> Map<String, Value> {
+    let mut settings = Map::new();
+
+    if let Some(Command::Sync {
+        direction: Some(direction),
+        ..
+    }) = &opt.command
+    {
+        settings.insert(String::from("direction"), json!(direction));
+    }
+    if opt.delete {
+        settings.insert(String::from("delete"), json!(true));
+    }
+    if let Some(inbox) = &opt.inbox {
+        settings.insert(String::from("inbox"), json!(inbox));
+    }
+    if let Some(trash_retention) = opt.trash_retention {
+        settings.insert(String::from("trash_retention"), json!(trash_retention));
+    }
+    if let Some(max_change) = opt.max_change {
+        settings.insert(String::from("max_change"), json!(max_change));
+    }
+    if let Some(concurrency) = opt.concurrency {
+        settings.insert(String::from("concurrency"), json!(concurrency));
+    }
+    if opt.no_index_cache {
+        settings.insert(String::from("index_cache"), json!(false));
+    }
+    if opt.require_complete_index {
+        settings.insert(String::from("require_complete_index"), json!(true));
+    }
+    if let Some(links) = opt.source_links {
+        settings.insert(String::from("source_links"), json!(links));
+    }
+    if let Some(links) = opt.destination_links {
+        settings.insert(String::from("destination_links"), json!(links));
+    }
+    if opt.hidden {
+        settings.insert(String::from("hidden"), json!(true));
+    }
+    if let Some(max_depth) = opt.max_depth {
+        settings.insert(String::from("max_depth"), json!(max_depth));
+    }
+    if opt.no_device_exclusions {
+        settings.insert(String::from("devices"), json!([]));
+    }
+
+    settings
+}
+
+fn print_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn validate(config: &Config) -> bool {
+    match config.validate() {
+        Ok(problems) if problems.is_empty() => {
+            info!("Config: {} is valid", config.get_path().to_str().unwrap());
+            true
+        }
+        Ok(problems) => {
+            for problem in problems {
+                error!("{}", problem);
+            }
+            false
+        }
+        Err(e) => {
+            error!("Error for parse config: {}", e);
+            false
+        }
+    }
+}
+
+/// Run config subcommand, returns success
+fn config_command(out: &Output, command: ConfigCommand, config: Config) -> bool {
+    let origin = Origin::User(config.get_path().clone());
+
+    match command {
+        ConfigCommand::Get { key } => match config.get(&key) {
+            Ok(Some(value)) => {
+                let line = print_value(&value);
+                out.record(Record::setting(key, value, &origin), &line);
+                true
+            }
+            Ok(None) => {
+                error!("Key: {} isn't set", key);
+                false
+            }
+            Err(e) => {
+                error!("Error for parse config: {}", e);
+                false
+            }
+        },
+        ConfigCommand::Set { key, value } => {
+            if let Err(e) = create_dir_for_path(config.get_path()) {
+                error!("Failed to create dir with error: {}", e);
+                return false;
+            }
+
+            match config.set(&key, parse_value(&value)) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("Error for set key: {}", e);
+                    false
+                }
+            }
+        }
+        ConfigCommand::Unset { key } => match config.unset(&key) {
+            Ok(true) => true,
+            Ok(false) => {
+                error!("Key: {} isn't set", key);
+                false
+            }
+            Err(e) => {
+                error!("Error for unset key: {}", e);
+                false
+            }
+        },
+        ConfigCommand::List => match config.list() {
+            Ok(values) => {
+                for (key, value) in values {
+                    let line = format!("{} = {}", key, print_value(&value));
+                    out.record(Record::setting(key, value, &origin), &line);
+                }
+                true
+            }
+            Err(e) => {
+                error!("Error for parse config: {}", e);
+                false
+            }
+        },
+        ConfigCommand::Edit => {
+            if let Err(e) = create_dir_for_path(config.get_path()) {
+                error!("Failed to create dir with error: {}", e);
+                return false;
+            }
+
+            let editor = env::var("VISUAL")
+                .or_else(|_| env::var("EDITOR"))
+                .unwrap_or_else(|_| String::from("vi"));
+
+            match process::Command::new(&editor)
+                .arg(config.get_path())
+                .status()
+            {
+                Ok(status) if status.success() => validate(&config),
+                Ok(status) => {
+                    error!("Editor: {} exited with {}", editor, status);
+                    false
+                }
+                Err(e) => {
+                    error!("Failed to run editor: {} with error: {}", editor, e);
+                    false
+                }
+            }
+        }
+        ConfigCommand::Validate => validate(&config),
+        ConfigCommand::Migrate => match config.migrate() {
+            Ok(Some(backup)) => {
+                info!(
+                    "Config migrated to version {}, old config saved to {}",
+                    CONFIG_VERSION,
+                    backup.to_str().unwrap()
+                );
+                true
+            }
+            Ok(None) => {
+                info!("Config has version {} already", CONFIG_VERSION);
+                true
+            }
+            Err(e) => {
+                error!("Error for migrate config: {}", e);
+                false
+            }
+        },
+    }
+}
+
 fn main() {
     let opt = Opt::from_args();
+    let out = Output::new(opt.format);
 
-    let config_path = match opt.config {
-        Some(config) => PathBuf::from(config),
-        _ => {
-            let mut default_path = PathBuf::from(config_dir().unwrap());
-            default_path.push(env!("CARGO_PKG_NAME"));
-            default_path.push("config.yaml");
+    let logger = Logger::new(logger::level(opt.verbose, opt.quiet));
+    let logger = match &opt.log_file {
+        Some(path) => logger.with_file(path.clone()),
+        None => Ok(logger),
+    };
+    match logger {
+        Ok(logger) => logger.init().unwrap(),
+        Err(e) => {
+            Logger::new(LevelFilter::Error).init().unwrap();
+            error!("Error for open log: {}", e);
+            process::exit(Outcome::ConfigError.code());
+        }
+    }
 
-            default_path
+    let config_path = match opt.config.clone().or_else(user_config_path) {
+        Some(config) => config,
+        None => {
+            error!("Config dir of user doesn't exist");
+            process::exit(Outcome::ConfigError.code());
         }
     };
 
-    let (source, destination) = match (opt.source, opt.destination) {
+    if let Some(Command::Config(command)) = opt.command {
+        let config = Config::new(config_path).with_strict(opt.strict);
+        let done = config_command(&out, command, config);
+        out.finish();
+        if !done {
+            process::exit(Outcome::ConfigError.code());
+        }
+        return;
+    }
+
+    // NOTE: paths of command line are written to one profile
+    if opt.write && opt.profile.len() > 1 {
+        error!(
+            "Write stores paths to one profile, {} profiles given",
+            opt.profile.len()
+        );
+        process::exit(Outcome::ConfigError.code());
+    }
+
+    let mut destinations = opt.destination.clone().into_iter();
+
+    // Profile of command line paths
+    let cli = match (opt.source.clone(), destinations.next()) {
         (Some(source), Some(destination)) => {
+            let name = opt
+                .profile
+                .first()
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+            let mut profile = Profile::new(source, destination);
+            profile.destinations = destinations.map(Destination::new).collect();
+
             if opt.write {
-                println!("Write paths to config {}", config_path.to_str().unwrap());
+                info!("Write paths to config {}", config_path.to_str().unwrap());
 
                 match create_dir_for_path(&config_path) {
                     Ok(()) => {
-                        let config = Config::new(config_path);
+                        let config = Config::new(config_path.clone());
 
-                        match config.store(source.clone(), destination.clone()) {
-                            Ok(()) => println!("Paths stored to config successfully"),
-                            Err(e) => println!("Error for store config: {}", e),
+                        match config.store_profile(&name, profile.clone()) {
+                            Ok(()) => info!("Paths stored to config successfully"),
+                            Err(e) => error!("Error for store config: {}", e),
                         }
                     }
                     Err(e) => {
-                        println!("Failed to create dir with error: {}", e);
+                        error!("Failed to create dir with error: {}", e);
                     }
                 }
             }
 
-            (source, destination)
+            Some((name, profile))
         }
-        (_, _) => {
-            println!("Parse config to extract source/destination paths");
+        (_, _) => None,
+    };
+
+    let mut layered = Layered::new();
+    let system_path = system_config_path();
+
+    for (path, origin) in [
+        (&system_path, Origin::System(system_path.clone())),
+        (&config_path, Origin::User(config_path.clone())),
+    ]
+    .iter()
+    {
+        if !path.exists() {
+            continue;
+        }
+
+        let config = Config::new(path.to_path_buf()).with_strict(opt.strict);
 
-            if !config_path.exists() {
-                println!("Config: {} doesn't exist", config_path.to_str().unwrap());
-                process::exit(1);
+        // NOTE: config of older version is migrated in memory, file with
+        // comments of user is rewritten only by `config migrate`
+        if let Ok(version) = config.version() {
+            if version < CONFIG_VERSION {
+                warn!(
+                    "Config {} has version {}, rewrite it to version {} by config migrate",
+                    path.to_str().unwrap(),
+                    version,
+                    CONFIG_VERSION
+                );
             }
+        }
 
-            let config = Config::new(config_path);
+        match config.layer() {
+            Ok(layer) => layered.merge(layer, origin.clone()),
+            Err(e) => {
+                error!("Error for parse config {}: {}", path.to_str().unwrap(), e);
+                process::exit(Outcome::ConfigError.code());
+            }
+        }
+    }
 
-            match config.parse() {
-                Ok(paths) => paths,
-                Err(e) => {
-                    println!("Error for parse config: {}", e);
-                    process::exit(1);
-                }
+    if cli.is_none() && layered.profile_names().is_empty() {
+        error!("Config: {} doesn't exist", config_path.to_str().unwrap());
+        process::exit(Outcome::ConfigError.code());
+    }
+
+    // Per-library configs are found by sources of all other layers
+    let mut sources = layered.clone();
+    if let Some((name, profile)) = &cli {
+        sources.merge(cli_profile(name, profile), Origin::Cli);
+    }
+
+    for name in sources.profile_names() {
+        let path = match sources.source_of(&name) {
+            Some(source) => source.join(LIBRARY_CONFIG),
+            None => continue,
+        };
+        if !path.exists() {
+            continue;
+        }
+
+        match Config::new(path.clone())
+            .with_strict(opt.strict)
+            .profile_layer()
+        {
+            Ok(settings) => layered.merge_profile(&name, settings, Origin::Library(path)),
+            Err(e) => {
+                error!("Error for parse config {}: {}", path.to_str().unwrap(), e);
+                process::exit(Outcome::ConfigError.code());
             }
         }
-    };
+    }
 
-    println!(
-        "Sync: local::{} <-> device::{}",
-        source.to_str().unwrap(),
-        destination.to_str().unwrap()
-    );
+    layered.merge_env(env::vars());
 
-    if !source.exists() {
-        println!("Source path: {} doesn't exist", source.to_str().unwrap());
-        process::exit(1);
+    if let Some((name, profile)) = &cli {
+        layered.merge(cli_profile(name, profile), Origin::Cli);
     }
+    layered.merge_all(cli_settings(&opt), Origin::Cli);
 
-    if !destination.exists() {
-        println!(
-            "Destination path: {} doesn't exist",
-            destination.to_str().unwrap()
-        );
-        process::exit(1);
-    }
-
-    let updater = Updater::new(source.clone(), destination.clone());
-
-    for book_status in updater.update(Update::OnlyFromForeignSync) {
-        println!(
-            "{} {} <= from: {} to: {}",
-            book_status.get_name(),
-            book_status.get_status(),
-            book_status
-                .get_src()
-                .strip_prefix(&source)
-                .unwrap()
-                .to_str()
-                .unwrap(),
-            book_status
-                .get_dst()
-                .strip_prefix(&source)
-                .unwrap()
-                .to_str()
-                .unwrap()
-        );
+    if opt.show_config {
+        match layered.effective() {
+            Ok(values) => {
+                for (path, value, origin) in values {
+                    let line = format!("{} = {} ({})", path, value, origin);
+                    out.record(Record::setting(path, value, &origin), &line);
+                }
+                out.finish();
+            }
+            Err(e) => {
+                error!("Error for parse config: {}", e);
+                process::exit(Outcome::ConfigError.code());
+            }
+        }
+        return;
     }
 
-    for book_status in updater.update(Update::OnlyFromLocal) {
-        println!(
-            "{} {} +> from: {} to: {}",
-            book_status.get_name(),
-            book_status.get_status(),
-            book_status
-                .get_src()
-                .strip_prefix(&source)
-                .unwrap()
-                .to_str()
-                .unwrap(),
-            book_status
-                .get_dst()
-                .strip_prefix(&destination)
-                .unwrap()
-                .to_str()
-                .unwrap()
-        );
+    let mut profiles = match layered.profiles() {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            error!("Error for parse config: {}", e);
+            process::exit(Outcome::ConfigError.code());
+        }
+    };
+
+    let names = match &cli {
+        Some((name, _)) => vec![name.clone()],
+        None if opt.profile.is_empty() => profiles.keys().cloned().collect(),
+        None => opt.profile.clone(),
+    };
+
+    // Bar is drawn only to terminal and hidden by verbosity, it would mix
+    // with details
+    let progress = opt.verbose == 0 && opt.quiet == 0 && io::stderr().is_terminal();
+    let confirm = (opt.confirm, opt.confirm_empty);
+    let mut report = SyncReport::new();
+
+    for name in names {
+        let profile = match profiles.remove(&name) {
+            Some(profile) => profile,
+            None => {
+                error!("Profile: {} doesn't exist in config", name);
+                process::exit(Outcome::ConfigError.code());
+            }
+        };
+
+        out.profile(&name);
+        report.merge(match &opt.command {
+            None => sync(&out, &profile, confirm, false, progress),
+            Some(Command::Sync { dry_run, .. }) => {
+                sync(&out, &profile, confirm, *dry_run, progress)
+            }
+            Some(Command::Status) => status(&out, &profile),
+            Some(Command::Diff { all }) => diff(&out, &profile, *all),
+            Some(Command::Ls { side }) => ls(&out, &profile, *side),
+            Some(Command::Verify) => verify(&out, &profile),
+            Some(Command::Preview { paths }) => preview(&out, &profile, paths),
+            Some(Command::Trash(command)) => trash(&out, &profile, command),
+            Some(Command::Config(_)) => unreachable!("config command runs before profiles"),
+        });
     }
 
-    for book_status in updater.update(Update::OnlyFromForeign) {
-        println!(
-            "{} {} <+ from: {} to: {}",
-            book_status.get_name(),
-            book_status.get_status(),
-            book_status
-                .get_src()
-                .strip_prefix(&destination)
-                .unwrap()
-                .to_str()
-                .unwrap(),
-            book_status
-                .get_dst()
-                .strip_prefix(&source)
-                .unwrap()
-                .to_str()
-                .unwrap()
+    if let None | Some(Command::Sync { .. }) = opt.command {
+        out.record(
+            Record::report(&report),
+            &format!(
+                "Total: {} transferred, {} bytes, {} failed",
+                report.get_transferred(),
+                report.get_bytes(),
+                report.get_failed()
+            ),
         );
     }
+
+    out.finish();
+
+    process::exit(report.outcome().code());
 }
@@ -0,0 +1,243 @@
+//! Metadata entity
+//!
+//! Book description: title, authors, series and other stuff
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Author {
+    #[serde(default)]
+    pub first: String,
+    #[serde(default)]
+    pub last: String,
+}
+
+impl Author {
+    pub fn new(first: &str, last: &str) -> Self {
+        Author {
+            first: first.trim().to_string(),
+            last: last.trim().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Author {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.first.is_empty(), self.last.is_empty()) {
+            (false, false) => write!(f, "{} {}", self.first, self.last),
+            (true, _) => write!(f, "{}", self.last),
+            (_, true) => write!(f, "{}", self.first),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<Author>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub year: Option<u32>,
+    /// Lowercase file extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+impl Metadata {
+    /// Metadata of book file: format by extension, embedded description of
+    /// FB2 books
+    pub fn read(path: &Path) -> Self {
+        let format = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        let metadata = match format.as_deref() {
+            Some("fb2") => match fs::read(path) {
+                Ok(content) => parse_fb2(&String::from_utf8_lossy(&content)),
+                Err(_) => Metadata::default(),
+            },
+            _ => Metadata::default(),
+        };
+
+        Metadata { format, ..metadata }
+    }
+
+    /// Names and values of set fields
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let authors = self
+            .authors
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<String>>()
+            .join(" & ");
+
+        vec![
+            ("title", self.title.clone()),
+            ("authors", Some(authors).filter(|a| !a.is_empty())),
+            ("series", self.series.clone()),
+            ("series_index", self.series_index.map(|i| i.to_string())),
+            ("year", self.year.map(|y| y.to_string())),
+            ("language", self.language.clone()),
+            ("format", self.format.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|v| (name, v)))
+        .collect()
+    }
+
+    /// Fill missing fields from other metadata
+    pub fn merge(self, other: Metadata) -> Self {
+        Metadata {
+            title: self.title.or(other.title),
+            authors: if self.authors.is_empty() {
+                other.authors
+            } else {
+                self.authors
+            },
+            language: self.language.or(other.language),
+            series: self.series.or(other.series),
+            series_index: self.series_index.or(other.series_index),
+            year: self.year.or(other.year),
+            format: self.format.or(other.format),
+        }
+    }
+}
+
+/// Text of first `<tag>...</tag>` element, attributes of tag are skipped
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}", tag);
+    let mut from = 0;
+
+    while let Some(start) = xml[from..].find(&open).map(|s| s + from) {
+        let rest = &xml[start + open.len()..];
+        match rest.chars().next() {
+            Some('>') | Some(' ') | Some('\t') | Some('\n') | Some('\r') => {
+                let body_start = start + open.len() + rest.find('>')? + 1;
+                let close = format!("</{}>", tag);
+                let body_end = body_start + xml[body_start..].find(&close)?;
+                return Some(&xml[body_start..body_end]);
+            }
+            _ => from = start + open.len(),
+        }
+    }
+
+    None
+}
+
+/// Value of attribute of first `<tag .../>` element
+fn attribute<'a>(xml: &'a str, tag: &str, name: &str) -> Option<&'a str> {
+    let open = format!("<{} ", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find('>')?;
+    let attributes = &xml[start..end];
+
+    let key = format!("{}=\"", name);
+    let value_start = attributes.find(&key)? + key.len();
+    let value_end = value_start + attributes[value_start..].find('"')?;
+
+    Some(&attributes[value_start..value_end])
+}
+
+fn text(value: Option<&str>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_fb2(xml: &str) -> Metadata {
+    let info = match element(xml, "title-info") {
+        Some(info) => info,
+        None => return Metadata::default(),
+    };
+
+    let mut authors = Vec::new();
+    let mut rest = info;
+    while let Some(author) = element(rest, "author") {
+        authors.push(Author::new(
+            element(author, "first-name").unwrap_or(""),
+            element(author, "last-name").unwrap_or(""),
+        ));
+        let end = rest.find("</author>").unwrap() + "</author>".len();
+        rest = &rest[end..];
+    }
+
+    Metadata {
+        title: text(element(info, "book-title")),
+        authors,
+        language: text(element(info, "lang")),
+        series: text(attribute(info, "sequence", "name")),
+        series_index: attribute(info, "sequence", "number").and_then(|n| n.trim().parse().ok()),
+        year: element(info, "date").and_then(|d| d.trim().get(..4)?.parse().ok()),
+        format: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn read_fb2() {
+        let metadata = Metadata::read(&PathBuf::from("tests/metadata/hobbit.fb2"));
+
+        assert_eq!(
+            metadata,
+            Metadata {
+                title: Some(String::from("The Hobbit")),
+                authors: vec![Author::new("John Ronald Reuel", "Tolkien")],
+                language: Some(String::from("en")),
+                series: Some(String::from("Middle-earth")),
+                series_index: Some(1),
+                year: Some(1937),
+                format: Some(String::from("fb2")),
+            }
+        );
+        assert_eq!(
+            format!("{}", metadata.authors[0]),
+            String::from("John Ronald Reuel Tolkien")
+        );
+    }
+
+    #[test]
+    fn read_format_only() {
+        let metadata = Metadata::read(&PathBuf::from("tests/iterate/file_one.txt"));
+
+        assert_eq!(
+            metadata,
+            Metadata {
+                format: Some(String::from("txt")),
+                ..Metadata::default()
+            }
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let metadata = Metadata {
+            title: Some(String::from("Title")),
+            ..Metadata::default()
+        }
+        .merge(Metadata {
+            title: Some(String::from("Other")),
+            year: Some(2000),
+            ..Metadata::default()
+        });
+
+        assert_eq!(metadata.title, Some(String::from("Title")));
+        assert_eq!(metadata.year, Some(2000));
+    }
+}
@@ -0,0 +1,247 @@
+//! Naming entity
+//!
+//! Book metadata inferred from file and folder names by regex rules
+
+use std::path::Path;
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use crate::config::is_default;
+use crate::metadata::{Author, Metadata};
+
+/// Named groups of pattern filling metadata fields
+const GROUPS: &[&str] = &[
+    "title",
+    "author",
+    "author_first",
+    "author_last",
+    "series",
+    "series_index",
+    "year",
+    "language",
+];
+
+/// Part of book path matched by pattern
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// File name without extension
+    #[default]
+    Name,
+    /// Path relative to library root without extension, separated by `/`
+    Path,
+}
+
+/// Rule of config: regex with named groups like `(?P<title>.+)`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NamingRule {
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub scope: Scope,
+}
+
+/// Authors of group value: `First Last` or `Last, First`, several authors are
+/// separated by `&` or `;`
+fn authors(value: &str) -> Vec<Author> {
+    value
+        .split(['&', ';'])
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|author| match author.find(',') {
+            Some(comma) => Author::new(&author[comma + 1..], &author[..comma]),
+            None => match author.rfind(' ') {
+                Some(space) => Author::new(&author[..space], &author[space + 1..]),
+                None => Author::new("", author),
+            },
+        })
+        .collect()
+}
+
+fn metadata_of(captures: &Captures) -> Metadata {
+    let text = |group: &str| {
+        captures
+            .name(group)
+            .map(|m| m.as_str().trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let number = |group: &str| text(group).and_then(|v| v.parse().ok());
+
+    let mut authors = text("author").map(|a| authors(&a)).unwrap_or_default();
+    if authors.is_empty() && (text("author_first").is_some() || text("author_last").is_some()) {
+        authors.push(Author::new(
+            &text("author_first").unwrap_or_default(),
+            &text("author_last").unwrap_or_default(),
+        ));
+    }
+
+    Metadata {
+        title: text("title"),
+        authors,
+        language: text("language"),
+        series: text("series"),
+        series_index: number("series_index"),
+        year: number("year"),
+        format: None,
+    }
+}
+
+/// Rules applied in order, first matched rule fills metadata
+#[derive(Debug, Default, Clone)]
+pub struct Naming {
+    rules: Vec<(NamingRule, Regex)>,
+}
+
+impl Naming {
+    pub fn new(rules: &[NamingRule]) -> Result<Self, String> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern).map_err(|e| {
+                    format!("fail to parse pattern: {} with error: {}", rule.pattern, e)
+                })?;
+
+                if let Some(group) = regex
+                    .capture_names()
+                    .flatten()
+                    .find(|group| !GROUPS.contains(group))
+                {
+                    return Err(format!(
+                        "unknown group: {} of pattern: {}",
+                        group, rule.pattern
+                    ));
+                }
+
+                Ok((rule.clone(), regex))
+            })
+            .collect::<Result<Vec<(NamingRule, Regex)>, String>>()?;
+
+        Ok(Naming { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    fn subject(scope: Scope, relative: &Path) -> String {
+        let path = relative.with_extension("");
+        match scope {
+            Scope::Name => path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            Scope::Path => path
+                .iter()
+                .map(|p| p.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        }
+    }
+
+    fn apply(rule: &NamingRule, regex: &Regex, relative: &Path) -> Option<Metadata> {
+        regex
+            .captures(&Naming::subject(rule.scope, relative))
+            .map(|captures| metadata_of(&captures))
+    }
+
+    /// Metadata by first rule matched path relative to library root
+    pub fn infer(&self, relative: &Path) -> Option<Metadata> {
+        self.rules
+            .iter()
+            .find_map(|(rule, regex)| Naming::apply(rule, regex, relative))
+    }
+
+    /// Metadata extracted by every rule, not matched rules give nothing
+    pub fn preview(&self, relative: &Path) -> Vec<(&NamingRule, Option<Metadata>)> {
+        self.rules
+            .iter()
+            .map(|(rule, regex)| (rule, Naming::apply(rule, regex, relative)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming() -> Naming {
+        Naming::new(&[
+            NamingRule {
+                pattern: String::from(r"^(?P<author>[^-]+) - (?P<title>.+) \((?P<year>\d{4})\)$"),
+                scope: Scope::Name,
+            },
+            NamingRule {
+                pattern: String::from(r"^(?P<series>.+) (?P<series_index>\d+) - (?P<title>.+)$"),
+                scope: Scope::Name,
+            },
+            NamingRule {
+                pattern: String::from(r"^(?P<author_last>[^/]+)/(?P<title>[^/]+)$"),
+                scope: Scope::Path,
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn infer() {
+        let naming = naming();
+
+        let metadata = naming
+            .infer(Path::new("Downloads/Tolkien, John - The Hobbit (1937).pdf"))
+            .unwrap();
+        assert_eq!(metadata.title, Some(String::from("The Hobbit")));
+        assert_eq!(metadata.authors, [Author::new("John", "Tolkien")]);
+        assert_eq!(metadata.year, Some(1937));
+
+        let metadata = naming
+            .infer(Path::new(
+                "Fiction/Middle-earth 03 - The Return of the King.epub",
+            ))
+            .unwrap();
+        assert_eq!(metadata.series, Some(String::from("Middle-earth")));
+        assert_eq!(metadata.series_index, Some(3));
+        assert_eq!(metadata.title, Some(String::from("The Return of the King")));
+
+        let metadata = naming.infer(Path::new("Tolkien/Silmarillion.fb2")).unwrap();
+        assert_eq!(metadata.authors, [Author::new("", "Tolkien")]);
+        assert_eq!(metadata.title, Some(String::from("Silmarillion")));
+
+        assert_eq!(naming.infer(Path::new("Fiction/Tolkien/notes.txt")), None);
+    }
+
+    #[test]
+    fn authors_of_group() {
+        assert_eq!(
+            authors("John Ronald Tolkien & Christopher Tolkien"),
+            [
+                Author::new("John Ronald", "Tolkien"),
+                Author::new("Christopher", "Tolkien")
+            ]
+        );
+    }
+
+    #[test]
+    fn preview() {
+        let naming = naming();
+        let preview = naming.preview(Path::new("Middle-earth 01 - The Hobbit.epub"));
+
+        assert_eq!(preview.len(), 3);
+        assert_eq!(preview[0].1, None);
+        assert_eq!(
+            preview[1].1.as_ref().and_then(|m| m.title.clone()),
+            Some(String::from("The Hobbit"))
+        );
+        assert_eq!(preview[2].1, None);
+    }
+
+    #[test]
+    fn wrong_rules() {
+        let rule = |pattern: &str| NamingRule {
+            pattern: String::from(pattern),
+            scope: Scope::Name,
+        };
+
+        assert!(Naming::new(&[rule("(?P<title>.+")]).is_err());
+        assert!(Naming::new(&[rule("(?P<publisher>.+)")]).is_err());
+    }
+}
@@ -2,8 +2,13 @@
 //!
 //! Command line options as StructOpt derive structure
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
+use crate::direction::Direction;
+use crate::indexer::Links;
+use crate::output::OutputFormat;
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = env!("CARGO_PKG_NAME"), about = env!("CARGO_PKG_DESCRIPTION"))]
 pub struct Opt {
@@ -11,15 +16,211 @@ pub struct Opt {
     #[structopt(short, long, parse(from_os_str))]
     pub source: Option<PathBuf>,
 
-    /// Destination directory, e-ink device folder
-    #[structopt(short, long, parse(from_os_str))]
-    pub destination: Option<PathBuf>,
+    /// Destination directory, e-ink device folder, can be repeated
+    #[structopt(short, long, parse(from_os_str), number_of_values = 1)]
+    pub destination: Vec<PathBuf>,
 
     /// Program configuration file with source/destination sync folders
-    #[structopt(short, long, parse(from_os_str))]
+    #[structopt(short, long, parse(from_os_str), global = true)]
     pub config: Option<PathBuf>,
 
-    /// Store options as new config
+    /// Print effective config with origin of every value and exit
+    #[structopt(long)]
+    pub show_config: bool,
+
+    /// Refuse config with unknown keys
+    #[structopt(long, global = true)]
+    pub strict: bool,
+
+    /// Config profile to sync or to write, all profiles are synced if not set
+    #[structopt(short, long, global = true, number_of_values = 1)]
+    pub profile: Vec<String>,
+
+    /// Store options as new config, to profile given by -p if set
     #[structopt(short, long)]
     pub write: bool,
+
+    /// Propagate deletions between sides, deleted books are moved to trash
+    #[structopt(long, global = true)]
+    pub delete: bool,
+
+    /// Source folder receiving books found only on destination
+    #[structopt(long, parse(from_os_str), global = true)]
+    pub inbox: Option<PathBuf>,
+
+    /// Days to keep deleted books in trash [default: 30]
+    #[structopt(long, global = true)]
+    pub trash_retention: Option<u64>,
+
+    /// Abort sync which changes more than given percent of books [default: 50]
+    #[structopt(long, global = true)]
+    pub max_change: Option<u8>,
+
+    /// Number of transfers running at once [default: 1]
+    #[structopt(long, global = true)]
+    pub concurrency: Option<usize>,
+
+    /// Index sides from scratch without reading or storing index cache
+    #[structopt(long, global = true)]
+    pub no_index_cache: bool,
+
+    /// Refuse sync if some files or folders of sides can't be read
+    #[structopt(long, global = true)]
+    pub require_complete_index: bool,
+
+    /// Symbolic links of source: ignore, follow or copy [default: ignore]
+    #[structopt(long, global = true)]
+    pub source_links: Option<Links>,
+
+    /// Symbolic links of destinations: ignore, follow or copy [default: ignore]
+    #[structopt(long, global = true)]
+    pub destination_links: Option<Links>,
+
+    /// Index hidden files and folders
+    #[structopt(long, global = true)]
+    pub hidden: bool,
+
+    /// Depth of folders walked below roots of sides, 0 is root only
+    #[structopt(long, global = true)]
+    pub max_depth: Option<usize>,
+
+    /// Walk system folders of devices like .kobo or LOST.DIR
+    #[structopt(long, global = true)]
+    pub no_device_exclusions: bool,
+
+    /// Output format: table, json or ndjson
+    #[structopt(long, global = true, default_value = "table")]
+    pub format: OutputFormat,
+
+    /// Confirm sync which changes more books than change limit
+    #[structopt(long, global = true)]
+    pub confirm: bool,
+
+    /// Confirm sync with side which has no books, but was synchronized before
+    #[structopt(long, global = true)]
+    pub confirm_empty: bool,
+
+    /// Print more details: -v debug, -vv trace
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    pub verbose: u8,
+
+    /// Print less: -q warnings and errors only, -qq errors only
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    pub quiet: u8,
+
+    /// Log file with timestamps and debug details, rotated by size
+    #[structopt(long, parse(from_os_str), global = true)]
+    pub log_file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Side of synchronization
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Source,
+    Destination,
+}
+
+impl FromStr for Side {
+    type Err = String;
+
+    fn from_str(side: &str) -> Result<Self, Self::Err> {
+        match side {
+            "source" => Ok(Side::Source),
+            "destination" => Ok(Side::Destination),
+            _ => Err(format!("unknown side: {}", side)),
+        }
+    }
+}
+
+// Command to run for selected profiles, sync if not set
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Synchronize sources with destinations
+    Sync {
+        /// Direction overriding profile direction: push, pull, both, mirror
+        /// or ignore
+        #[structopt(long)]
+        direction: Option<Direction>,
+        /// Print planned transfers without doing them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Show number of differing books and pending transfers
+    Status,
+    /// Compare sides book by book
+    Diff {
+        /// Show equal books too
+        #[structopt(long)]
+        all: bool,
+    },
+    /// List books of source or destination side
+    Ls {
+        /// source or destination
+        #[structopt(default_value = "source")]
+        side: Side,
+    },
+    /// Check that books present on both sides have equal content
+    Verify,
+    /// Manage config, keys are dotted paths like profiles.kobo.direction
+    Config(ConfigCommand),
+    /// Manage trash of books deleted by sync
+    Trash(TrashCommand),
+    /// Print metadata extracted from book names by every naming rule
+    Preview {
+        /// Books or folders to preview, source of profile if not set
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ConfigCommand {
+    /// Print value of key
+    Get { key: String },
+    /// Set value of key, value is YAML: text, number, [list] or {map}
+    ///
+    /// Other keys keep their order, comments are lost. Config of older
+    /// version is rewritten in current layout, old config is kept as backup
+    Set { key: String, value: String },
+    /// Remove key, other keys keep their order, comments are lost
+    Unset { key: String },
+    /// Print all keys with values
+    List,
+    /// Open config in $VISUAL or $EDITOR and validate it after
+    Edit,
+    /// Check that config is parsed, paths exist and options are consistent
+    Validate,
+    /// Rewrite config of older version in current layout, comments are lost,
+    /// old config is kept as backup
+    Migrate,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum TrashCommand {
+    /// List batches of trash with their books
+    List {
+        /// source or destination
+        #[structopt(default_value = "source")]
+        side: Side,
+    },
+    /// Move books of batch back to their paths
+    Restore {
+        /// Batch printed by list
+        batch: String,
+        /// source or destination
+        #[structopt(default_value = "source")]
+        side: Side,
+    },
+    /// Remove all batches of trash
+    Empty {
+        /// source or destination
+        #[structopt(default_value = "source")]
+        side: Side,
+        /// Remove only batches older than trash retention
+        #[structopt(long)]
+        expired: bool,
+    },
 }
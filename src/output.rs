@@ -0,0 +1,396 @@
+//! Output entity
+//!
+//! Results of commands as table lines or as JSON/NDJSON records for scripts
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::book::Book;
+use crate::bookshelf::Bookshelf;
+use crate::compare::{Comparison, Difference};
+use crate::layered::Origin;
+use crate::metadata::Metadata;
+use crate::naming::NamingRule;
+use crate::report::{Outcome, SyncReport};
+use crate::updater::{BookStatus, BookTransferStatus, Update};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Lines for human
+    #[default]
+    Table,
+    /// Single array of records printed after command
+    Json,
+    /// Record per line printed as soon as it is ready
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(format!("unknown format: {}", format)),
+        }
+    }
+}
+
+/// Result of command, every record gets `record` key with its kind and
+/// `profile` key with name of profile
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum Record {
+    /// Transfer done by sync or planned by dry run
+    Transfer {
+        update: Update,
+        name: String,
+        src: PathBuf,
+        dst: PathBuf,
+        status: String,
+        error_kind: Option<String>,
+        error: Option<String>,
+        bytes: Option<u64>,
+        planned: bool,
+    },
+    /// Book of side listing
+    Book {
+        root: PathBuf,
+        path: PathBuf,
+        name: String,
+        bytes: Option<u64>,
+    },
+    /// Book compared on sides, paths are relative to side roots
+    Comparison {
+        source: PathBuf,
+        destination: PathBuf,
+        name: String,
+        source_path: Option<PathBuf>,
+        destination_path: Option<PathBuf>,
+        difference: String,
+        error: Option<String>,
+    },
+    /// Numbers of books by difference and pending transfers by update
+    Status {
+        source: PathBuf,
+        destination: PathBuf,
+        differences: BTreeMap<String, usize>,
+        pending: BTreeMap<Update, usize>,
+    },
+    /// Batch of trash with paths of books relative to root
+    TrashBatch {
+        root: PathBuf,
+        batch: String,
+        books: Vec<PathBuf>,
+    },
+    /// Book moved back from batch of trash
+    Restore {
+        root: PathBuf,
+        batch: String,
+        name: String,
+        path: PathBuf,
+        status: String,
+        error_kind: Option<String>,
+        error: Option<String>,
+    },
+    /// Metadata extracted by every naming rule from path of book, fields
+    /// are null for rule which doesn't match
+    Preview {
+        path: PathBuf,
+        matches: Vec<RuleMatch>,
+    },
+    /// Value of config key with layer which set it
+    Setting {
+        key: String,
+        value: Value,
+        origin: String,
+    },
+    /// Results of sync aggregated over all profiles, printed last
+    Report {
+        outcome: Outcome,
+        #[serde(flatten)]
+        report: SyncReport,
+    },
+}
+
+/// Metadata fields extracted by naming rule
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RuleMatch {
+    pattern: String,
+    fields: Option<BTreeMap<String, String>>,
+}
+
+impl Record {
+    pub fn transfer(update: Update, book_status: &BookStatus, planned: bool) -> Self {
+        let (status, error) = match book_status.get_status() {
+            BookTransferStatus::Error(e) => (String::from("error"), Some(e.clone())),
+            status => (status.to_string().to_lowercase(), None),
+        };
+
+        Record::Transfer {
+            update,
+            name: book_status.get_name().clone(),
+            src: book_status.get_src().clone(),
+            dst: book_status.get_dst().clone(),
+            status,
+            error_kind: book_status.get_error_kind().cloned(),
+            error,
+            bytes: book_status.get_bytes(),
+            planned,
+        }
+    }
+
+    pub fn restore(root: PathBuf, batch: &str, book_status: &BookStatus) -> Self {
+        let (status, error) = match book_status.get_status() {
+            BookTransferStatus::Error(e) => (String::from("error"), Some(e.clone())),
+            status => (status.to_string().to_lowercase(), None),
+        };
+
+        Record::Restore {
+            path: book_status
+                .get_dst()
+                .strip_prefix(&root)
+                .unwrap_or_else(|_| book_status.get_dst())
+                .to_path_buf(),
+            root,
+            batch: batch.to_string(),
+            name: book_status.get_name().clone(),
+            status,
+            error_kind: book_status.get_error_kind().cloned(),
+            error,
+        }
+    }
+
+    pub fn report(report: &SyncReport) -> Self {
+        Record::Report {
+            outcome: report.outcome(),
+            report: report.clone(),
+        }
+    }
+
+    pub fn preview(path: &Path, preview: &[(&NamingRule, Option<Metadata>)]) -> Self {
+        Record::Preview {
+            path: path.to_path_buf(),
+            matches: preview
+                .iter()
+                .map(|(rule, metadata)| RuleMatch {
+                    pattern: rule.pattern.clone(),
+                    fields: metadata.as_ref().map(|m| {
+                        m.fields()
+                            .into_iter()
+                            .map(|(name, value)| (name.to_string(), value))
+                            .collect()
+                    }),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn setting(key: String, value: Value, origin: &Origin) -> Self {
+        Record::Setting {
+            key,
+            value,
+            origin: origin.to_string(),
+        }
+    }
+
+    pub fn book(books: &Bookshelf, book: &Book) -> Self {
+        Record::Book {
+            root: books.get_path().clone(),
+            path: books.relative_path(book).to_path_buf(),
+            name: book.get_name().clone(),
+            bytes: book.get_path().metadata().ok().map(|m| m.len()),
+        }
+    }
+
+    pub fn comparison(source: PathBuf, destination: PathBuf, comparison: &Comparison) -> Self {
+        let error = match comparison.get_difference() {
+            Difference::Unreadable(e) => Some(e.clone()),
+            _ => None,
+        };
+
+        Record::Comparison {
+            source,
+            destination,
+            name: comparison.get_name().clone(),
+            source_path: comparison.get_local().cloned(),
+            destination_path: comparison.get_foreign().cloned(),
+            difference: comparison.get_difference().name().to_string(),
+            error,
+        }
+    }
+}
+
+/// Printer of command results to stdout, messages for human are logged
+pub struct Output {
+    format: OutputFormat,
+    profile: RefCell<Option<String>>,
+    records: RefCell<Vec<Value>>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Output {
+            format,
+            profile: RefCell::new(None),
+            records: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn get_format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// Profile of following records
+    pub fn profile(&self, name: &str) {
+        self.profile.replace(Some(name.to_string()));
+        info!("Profile: {}", name);
+    }
+
+    fn value(&self, record: &Record) -> Value {
+        let mut value = serde_json::to_value(record).unwrap();
+
+        if let (Value::Object(map), Some(profile)) = (&mut value, self.profile.borrow().as_ref()) {
+            map.insert(String::from("profile"), Value::String(profile.clone()));
+        }
+
+        value
+    }
+
+    /// Print record, table format prints line of record instead
+    pub fn record(&self, record: Record, line: &str) {
+        match self.format {
+            OutputFormat::Table => println!("{}", line),
+            OutputFormat::Json => self.records.borrow_mut().push(self.value(&record)),
+            OutputFormat::Ndjson => println!("{}", self.value(&record)),
+        }
+    }
+
+    /// Print records collected for JSON array
+    pub fn finish(&self) {
+        if self.format == OutputFormat::Json {
+            let records = Value::Array(self.records.replace(Vec::new()));
+            println!("{}", serde_json::to_string_pretty(&records).unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!("ndjson".parse(), Ok(OutputFormat::Ndjson));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn transfer_record() {
+        let book = Book::from(PathBuf::from("tests/iterate/file_one.txt"));
+        let book_status = BookStatus::new(
+            &book,
+            PathBuf::from("/device/file_one.txt"),
+            BookTransferStatus::Copied,
+        );
+
+        let output = Output::new(OutputFormat::Json);
+        output.profile.replace(Some(String::from("kobo")));
+
+        assert_eq!(
+            output.value(&Record::transfer(Update::OnlyFromLocal, &book_status, true)),
+            json!({
+                "record": "transfer",
+                "profile": "kobo",
+                "update": "only_from_local",
+                "name": "file_one.txt",
+                "src": "tests/iterate/file_one.txt",
+                "dst": "/device/file_one.txt",
+                "status": "copied",
+                "error_kind": null,
+                "error": null,
+                "bytes": book.get_path().metadata().unwrap().len(),
+                "planned": true,
+            })
+        );
+    }
+
+    #[test]
+    fn preview_record() {
+        let rules = [
+            NamingRule {
+                pattern: String::from("^(?P<title>.+)$"),
+                scope: Default::default(),
+            },
+            NamingRule {
+                pattern: String::from("^(?P<year>\\d{4})$"),
+                scope: Default::default(),
+            },
+        ];
+        let metadata = Metadata {
+            title: Some(String::from("The Hobbit")),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(Record::preview(
+                Path::new("The Hobbit.epub"),
+                &[(&rules[0], Some(metadata)), (&rules[1], None)]
+            ))
+            .unwrap(),
+            json!({
+                "record": "preview",
+                "path": "The Hobbit.epub",
+                "matches": [
+                    {"pattern": "^(?P<title>.+)$", "fields": {"title": "The Hobbit"}},
+                    {"pattern": "^(?P<year>\\d{4})$", "fields": null},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn setting_record() {
+        assert_eq!(
+            serde_json::to_value(Record::setting(
+                String::from("profiles.kobo.max_change"),
+                json!(20),
+                &Origin::Env(String::from("EBSYNC_MAX_CHANGE"))
+            ))
+            .unwrap(),
+            json!({
+                "record": "setting",
+                "key": "profiles.kobo.max_change",
+                "value": 20,
+                "origin": "environment EBSYNC_MAX_CHANGE",
+            })
+        );
+    }
+
+    #[test]
+    fn status_record() {
+        let mut pending = BTreeMap::new();
+        pending.insert(Update::OnlyFromForeign, 2);
+
+        assert_eq!(
+            serde_json::to_value(Record::Status {
+                source: PathBuf::from("/library"),
+                destination: PathBuf::from("/device"),
+                differences: BTreeMap::new(),
+                pending,
+            })
+            .unwrap()["pending"],
+            json!({"only_from_foreign": 2})
+        );
+    }
+}
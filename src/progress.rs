@@ -0,0 +1,219 @@
+//! Progress entity
+//!
+//! Files and bytes of transfers done and remaining, reported to observer
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::updater::{BookStatus, BookTransferStatus};
+
+/// State of transfers of one update, bytes are counted for copies only
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Progress {
+    files_done: usize,
+    files_total: usize,
+    bytes_done: u64,
+    bytes_total: u64,
+    current: Option<PathBuf>,
+}
+
+impl Progress {
+    pub fn get_files_done(&self) -> usize {
+        self.files_done
+    }
+
+    pub fn get_files_total(&self) -> usize {
+        self.files_total
+    }
+
+    pub fn get_bytes_done(&self) -> u64 {
+        self.bytes_done
+    }
+
+    pub fn get_bytes_total(&self) -> u64 {
+        self.bytes_total
+    }
+
+    /// Source of transfer in progress
+    pub fn get_current(&self) -> Option<&PathBuf> {
+        self.current.as_ref()
+    }
+
+    pub fn files_remaining(&self) -> usize {
+        self.files_total - self.files_done
+    }
+
+    pub fn bytes_remaining(&self) -> u64 {
+        self.bytes_total.saturating_sub(self.bytes_done)
+    }
+}
+
+/// Receiver of progress of transfers, called while update runs
+pub trait Observer: Send + Sync {
+    /// Transfers are planned, nothing is done yet
+    fn started(&self, _progress: &Progress) {}
+
+    /// File is started, bytes of file are copied or file is done
+    fn progressed(&self, _progress: &Progress) {}
+
+    /// Every planned transfer is done or failed
+    fn finished(&self, _progress: &Progress) {}
+}
+
+fn copied_bytes(book_status: &BookStatus) -> u64 {
+    match book_status.get_status() {
+        BookTransferStatus::Copied => book_status.get_bytes().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Progress of planned transfers passed to observer
+pub(crate) struct Tracker<'a> {
+    observer: &'a dyn Observer,
+    progress: Mutex<Progress>,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(observer: &'a dyn Observer, plan: &[BookStatus]) -> Self {
+        let progress = Progress {
+            files_total: plan.len(),
+            bytes_total: plan.iter().map(copied_bytes).sum(),
+            ..Progress::default()
+        };
+        observer.started(&progress);
+
+        Tracker {
+            observer,
+            progress: Mutex::new(progress),
+        }
+    }
+
+    fn update<F: FnOnce(&mut Progress)>(&self, change: F) {
+        let mut progress = self.progress.lock().unwrap();
+        change(&mut progress);
+        self.observer.progressed(&progress);
+    }
+
+    pub(crate) fn start(&self, book_status: &BookStatus) {
+        self.update(|p| p.current = Some(book_status.get_src().clone()));
+    }
+
+    pub(crate) fn copy(&self, bytes: u64) {
+        self.update(|p| p.bytes_done += bytes);
+    }
+
+    /// Transfer is done, bytes planned but not copied are counted as done
+    pub(crate) fn done(&self, planned: &BookStatus, copied: u64) {
+        let rest = copied_bytes(planned).saturating_sub(copied);
+        self.update(|p| {
+            p.files_done += 1;
+            p.bytes_done += rest;
+        });
+    }
+
+    pub(crate) fn finish(&self) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.current = None;
+        self.observer.finished(&progress);
+    }
+}
+
+/// Writer reporting written bytes to tracker
+struct Counted<'a, 't> {
+    file: File,
+    tracker: &'a Tracker<'t>,
+    written: u64,
+}
+
+impl Write for Counted<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        self.tracker.copy(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Copy file like `fs::copy` reporting copied bytes, result has bytes copied
+/// before error too
+pub(crate) fn copy(src: &Path, dst: &Path, tracker: &Tracker) -> (u64, io::Result<u64>) {
+    let mut counted = None;
+
+    let result = (|| {
+        let mut source = File::open(src)?;
+        let permissions = source.metadata()?.permissions();
+        let counted = counted.get_or_insert(Counted {
+            file: File::create(dst)?,
+            tracker,
+            written: 0,
+        });
+        let bytes = io::copy(&mut source, counted)?;
+        fs::set_permissions(dst, permissions)?;
+        Ok(bytes)
+    })();
+
+    (counted.map(|c| c.written).unwrap_or(0), result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::Book;
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Mutex<Vec<Progress>>,
+    }
+
+    impl Observer for Recorder {
+        fn progressed(&self, progress: &Progress) {
+            self.calls.lock().unwrap().push(progress.clone());
+        }
+
+        fn finished(&self, progress: &Progress) {
+            self.calls.lock().unwrap().push(progress.clone());
+        }
+    }
+
+    #[test]
+    fn track_transfers() {
+        let book = Book::from(PathBuf::from("tests/iterate/file_one.txt"));
+        let size = book.get_path().metadata().unwrap().len();
+        let plan = [
+            BookStatus::new(
+                &book,
+                PathBuf::from("/device/one.txt"),
+                BookTransferStatus::Copied,
+            ),
+            BookStatus::new(
+                &book,
+                PathBuf::from("/device/two.txt"),
+                BookTransferStatus::Moved,
+            ),
+        ];
+
+        let recorder = Recorder::default();
+        let tracker = Tracker::new(&recorder, &plan);
+        tracker.start(&plan[0]);
+        tracker.copy(1);
+        tracker.done(&plan[0], 1);
+        tracker.start(&plan[1]);
+        tracker.done(&plan[1], 0);
+        tracker.finish();
+
+        let calls = recorder.calls.into_inner().unwrap();
+        assert_eq!(calls[0].get_current(), Some(book.get_path()));
+        assert_eq!(calls[1].get_bytes_done(), 1);
+        assert_eq!(calls[2].get_files_done(), 1);
+        assert_eq!(calls[2].get_bytes_done(), size);
+        assert_eq!(calls[5].files_remaining(), 0);
+        assert_eq!(calls[5].bytes_remaining(), 0);
+        assert_eq!(calls[5].get_current(), None);
+    }
+}
@@ -0,0 +1,252 @@
+//! Report entity
+//!
+//! Results of synchronization aggregated over books, destinations and
+//! profiles
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::indexer::{IndexError, IndexReport};
+use crate::updater::{BookStatus, BookTransferStatus};
+
+/// Result of run, mapped to exit code of command line tool
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    /// Some books or destinations failed, others are synced
+    PartialFailure,
+    /// Nothing is synced: every transfer or every destination failed
+    TotalFailure,
+    /// Config can't be loaded or has wrong settings
+    ConfigError,
+    /// Sync refused by safety guard
+    SafetyAbort,
+}
+
+impl Outcome {
+    /// Exit code, 1 is left for command line usage errors
+    pub fn code(&self) -> i32 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::PartialFailure => 2,
+            Outcome::TotalFailure => 3,
+            Outcome::ConfigError => 4,
+            Outcome::SafetyAbort => 5,
+        }
+    }
+}
+
+/// Failed transfer of book
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub name: String,
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub error_kind: Option<String>,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct SyncReport {
+    /// Destinations handled to the end
+    synced: usize,
+    transferred: usize,
+    failed: usize,
+    /// Size of transferred books
+    bytes: u64,
+    /// Number of transferred books by status
+    statuses: BTreeMap<String, usize>,
+    failures: Vec<Failure>,
+    /// Destinations failed as whole, like missing path
+    errors: Vec<String>,
+    config_errors: Vec<String>,
+    /// Reasons of safety guard refusals
+    aborts: Vec<String>,
+    /// Entries of sides missing from index
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    index_errors: Vec<IndexError>,
+    /// FIFOs, sockets and devices found on sides, they aren't synced
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    special_files: Vec<PathBuf>,
+}
+
+impl SyncReport {
+    pub fn new() -> Self {
+        SyncReport::default()
+    }
+
+    /// Report of destination handled to the end
+    pub fn synced() -> Self {
+        SyncReport {
+            synced: 1,
+            ..SyncReport::default()
+        }
+    }
+
+    pub fn get_transferred(&self) -> usize {
+        self.transferred
+    }
+
+    pub fn get_failed(&self) -> usize {
+        self.failed
+    }
+
+    pub fn get_bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn get_failures(&self) -> &Vec<Failure> {
+        &self.failures
+    }
+
+    pub fn get_index_errors(&self) -> &Vec<IndexError> {
+        &self.index_errors
+    }
+
+    pub fn add(&mut self, book_status: &BookStatus) {
+        match book_status.get_status() {
+            BookTransferStatus::Error(e) => {
+                self.failed += 1;
+                self.failures.push(Failure {
+                    name: book_status.get_name().clone(),
+                    src: book_status.get_src().clone(),
+                    dst: book_status.get_dst().clone(),
+                    error_kind: book_status.get_error_kind().cloned(),
+                    error: e.clone(),
+                });
+            }
+            status => {
+                self.transferred += 1;
+                self.bytes += book_status.get_bytes().unwrap_or(0);
+                *self
+                    .statuses
+                    .entry(status.to_string().to_lowercase())
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// Destination finished
+    pub fn finish(&mut self) {
+        self.synced += 1;
+    }
+
+    /// Destination failed as whole
+    pub fn error(&mut self, error: String) {
+        self.errors.push(error);
+    }
+
+    pub fn config_error(&mut self, error: String) {
+        self.config_errors.push(error);
+    }
+
+    pub fn abort(&mut self, reason: String) {
+        self.aborts.push(reason);
+    }
+
+    /// Errors and special files of index of destination, they don't fail
+    /// sync
+    pub fn index(&mut self, report: IndexReport) {
+        self.index_errors
+            .extend(report.get_errors().iter().cloned());
+        self.special_files
+            .extend(report.get_special().iter().cloned());
+    }
+
+    pub fn merge(&mut self, other: SyncReport) {
+        self.synced += other.synced;
+        self.transferred += other.transferred;
+        self.failed += other.failed;
+        self.bytes += other.bytes;
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_insert(0) += count;
+        }
+        self.failures.extend(other.failures);
+        self.errors.extend(other.errors);
+        self.config_errors.extend(other.config_errors);
+        self.aborts.extend(other.aborts);
+        self.index_errors.extend(other.index_errors);
+        self.special_files.extend(other.special_files);
+    }
+
+    /// Worst outcome: config errors, then safety aborts, then failures
+    pub fn outcome(&self) -> Outcome {
+        if !self.config_errors.is_empty() {
+            Outcome::ConfigError
+        } else if !self.aborts.is_empty() {
+            Outcome::SafetyAbort
+        } else if self.failed == 0 && self.errors.is_empty() {
+            Outcome::Success
+        } else if self.synced == 0 || (self.transferred == 0 && self.failed > 0) {
+            Outcome::TotalFailure
+        } else {
+            Outcome::PartialFailure
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::book::Book;
+
+    fn book_status(failed: bool) -> BookStatus {
+        let status = BookStatus::new(
+            &Book::from(PathBuf::from("tests/iterate/file_one.txt")),
+            PathBuf::from("/device/file_one.txt"),
+            BookTransferStatus::Copied,
+        );
+
+        if failed {
+            status.with_error(&io::Error::from(io::ErrorKind::PermissionDenied))
+        } else {
+            status
+        }
+    }
+
+    #[test]
+    fn add() {
+        let mut report = SyncReport::synced();
+        report.add(&book_status(false));
+        report.add(&book_status(true));
+
+        assert_eq!(report.get_transferred(), 1);
+        assert_eq!(report.get_failed(), 1);
+        assert_eq!(report.get_bytes(), book_status(false).get_bytes().unwrap());
+        assert_eq!(
+            report.get_failures()[0].error_kind,
+            Some(String::from("permission_denied"))
+        );
+        assert_eq!(report.outcome(), Outcome::PartialFailure);
+    }
+
+    #[test]
+    fn outcome() {
+        assert_eq!(SyncReport::synced().outcome(), Outcome::Success);
+
+        let mut report = SyncReport::synced();
+        report.add(&book_status(true));
+        assert_eq!(report.outcome(), Outcome::TotalFailure);
+
+        let mut report = SyncReport::new();
+        report.error(String::from("destination doesn't exist"));
+        assert_eq!(report.outcome(), Outcome::TotalFailure);
+
+        let mut missing = SyncReport::new();
+        missing.error(String::from("destination doesn't exist"));
+        let mut report = SyncReport::synced();
+        report.merge(missing);
+        assert_eq!(report.outcome(), Outcome::PartialFailure);
+
+        report.abort(String::from("too many changes"));
+        assert_eq!(report.outcome(), Outcome::SafetyAbort);
+        report.config_error(String::from("unknown key"));
+        assert_eq!(report.outcome(), Outcome::ConfigError);
+        assert_eq!(report.outcome().code(), 4);
+    }
+}
@@ -0,0 +1,236 @@
+//! Sync state entity
+//!
+//! Books known to be synchronized after last run, used to detect deletions
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::book::Book;
+use crate::bookshelf::Bookshelf;
+
+/// State file name, stored in root of foreign side
+pub const STATE_FILE: &str = ".ebsync-state.yaml";
+
+pub struct SyncState {
+    path: PathBuf,
+    books: BTreeSet<String>,
+    inbox: BTreeMap<PathBuf, PathBuf>,
+    pairs: BTreeMap<PathBuf, PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncStateStorage {
+    books: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    inbox: BTreeMap<PathBuf, PathBuf>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pairs: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl SyncState {
+    pub fn new(path: PathBuf) -> Self {
+        SyncState {
+            path,
+            books: BTreeSet::new(),
+            inbox: BTreeMap::new(),
+            pairs: BTreeMap::new(),
+        }
+    }
+
+    /// Load state, missing state file gives empty state (first sync)
+    ///
+    /// Unreadable state is error, it must not be replaced by empty state on
+    /// next store
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(SyncState::new(path));
+        }
+
+        let mut f =
+            File::open(&path).map_err(|e| format!("fail to open state with error: {}", e))?;
+
+        let mut s = String::new();
+        f.read_to_string(&mut s)
+            .map_err(|e| format!("fail to read state with error: {}", e))?;
+
+        let SyncStateStorage {
+            books,
+            inbox,
+            pairs,
+        } = serde_yaml::from_str(&s).map_err(|e| {
+            format!(
+                "fail to parse state {} with error: {}, fix or remove it",
+                path.display(),
+                e
+            )
+        })?;
+
+        Ok(SyncState {
+            path,
+            books,
+            inbox,
+            pairs,
+        })
+    }
+
+    pub fn store(&self) -> Result<(), String> {
+        let mut f = File::create(&self.path)
+            .map_err(|e| format!("fail to create state with error: {}", e))?;
+
+        let serialized = serde_yaml::to_string(&SyncStateStorage {
+            books: self.books.clone(),
+            inbox: self.inbox.clone(),
+            pairs: self.pairs.clone(),
+        })
+        .map_err(|e| format!("fail to serialize state with error: {}", e))?;
+
+        f.write_all(serialized.as_bytes())
+            .map_err(|e| format!("fail to store state with error: {}", e))
+    }
+
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Replace known books with books from bookshelf
+    pub fn record(&mut self, bookshelf: &Bookshelf) {
+        self.books = bookshelf.iter().map(|b| b.get_name().to_string()).collect();
+    }
+
+    pub fn have(&self, book: &Book) -> bool {
+        self.books.contains(book.get_name())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+
+    /// Remember book copied from foreign side to local inbox, paths are
+    /// relative to roots of sides
+    pub fn add_inbox(&mut self, foreign: PathBuf, local: PathBuf) {
+        self.inbox.insert(foreign, local);
+    }
+
+    /// Foreign paths of books copied to local inbox with their local paths
+    pub fn get_inbox(&self) -> &BTreeMap<PathBuf, PathBuf> {
+        &self.inbox
+    }
+
+    /// Forget inbox books not matching predicate of foreign path
+    pub fn retain_inbox<P>(&mut self, predicate: P)
+    where
+        P: Fn(&PathBuf) -> bool,
+    {
+        self.inbox.retain(|foreign, _| predicate(foreign));
+    }
+
+    /// Remember local book placed on foreign side by path template, paths
+    /// are relative to roots of sides
+    pub fn add_pair(&mut self, foreign: PathBuf, local: PathBuf) {
+        self.pairs.insert(foreign, local);
+    }
+
+    /// Foreign paths of books placed by path template with their local paths
+    pub fn get_pairs(&self) -> &BTreeMap<PathBuf, PathBuf> {
+        &self.pairs
+    }
+
+    /// Forget pairs not matching predicate of foreign path
+    pub fn retain_pairs<P>(&mut self, predicate: P)
+    where
+        P: Fn(&PathBuf) -> bool,
+    {
+        self.pairs.retain(|foreign, _| predicate(foreign));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn missing_state_is_empty() {
+        let state = SyncState::load(PathBuf::from("tests/state/missing.yaml")).unwrap();
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn broken_state_is_error() {
+        let state = SyncState::load(PathBuf::from("tests/state/test_state_broken.yaml"));
+        assert!(state.is_err());
+    }
+
+    #[test]
+    fn store_and_load() {
+        let path = PathBuf::from("tests/state/test_state_store.yaml");
+
+        let mut bs = Bookshelf::new();
+        bs.add(Book::new(String::from("file_one.txt")));
+        bs.add(Book::new(String::from("file_two.txt")));
+
+        let mut state = SyncState::new(path.clone());
+        state.record(&bs);
+        assert_eq!(state.store(), Ok(()));
+
+        let state = SyncState::load(path.clone()).unwrap();
+        assert!(state.have(&Book::new(String::from("file_one.txt"))));
+        assert!(state.have(&Book::new(String::from("file_two.txt"))));
+        assert!(!state.have(&Book::new(String::from("file_three.txt"))));
+        assert!(state.get_inbox().is_empty());
+        assert!(state.get_pairs().is_empty());
+    }
+
+    #[test]
+    fn store_and_load_inbox() {
+        let path = PathBuf::from("tests/state/test_state_inbox.yaml");
+
+        let mut state = SyncState::new(path.clone());
+        state.add_inbox(
+            PathBuf::from("Downloads/book.epub"),
+            PathBuf::from("Inbox/book.epub"),
+        );
+        state.add_inbox(
+            PathBuf::from("Downloads/other.epub"),
+            PathBuf::from("Inbox/other.epub"),
+        );
+        state.retain_inbox(|foreign| foreign != &PathBuf::from("Downloads/other.epub"));
+        assert_eq!(state.store(), Ok(()));
+
+        let state = SyncState::load(path).unwrap();
+        assert_eq!(
+            state.get_inbox().iter().collect::<Vec<_>>(),
+            [(
+                &PathBuf::from("Downloads/book.epub"),
+                &PathBuf::from("Inbox/book.epub")
+            )]
+        );
+    }
+
+    #[test]
+    fn store_and_load_pairs() {
+        let path = PathBuf::from("tests/state/test_state_pairs.yaml");
+
+        let mut state = SyncState::new(path.clone());
+        state.add_pair(
+            PathBuf::from("Tolkien/01 - The Hobbit.fb2"),
+            PathBuf::from("Fiction/hobbit.fb2"),
+        );
+        assert_eq!(state.store(), Ok(()));
+
+        let state = SyncState::load(path).unwrap();
+        assert!(state.get_inbox().is_empty());
+        assert_eq!(
+            state.get_pairs().iter().collect::<Vec<_>>(),
+            [(
+                &PathBuf::from("Tolkien/01 - The Hobbit.fb2"),
+                &PathBuf::from("Fiction/hobbit.fb2")
+            )]
+        );
+    }
+}
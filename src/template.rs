@@ -0,0 +1,223 @@
+//! Template entity
+//!
+//! Path of book built from its metadata, e.g.
+//! `{authors[0].last}/{series}/{series_index:02} - {title}.{ext}`
+
+use std::path::{Path, PathBuf};
+
+use crate::book::Book;
+use crate::metadata::Metadata;
+use crate::utility::path_safe;
+
+const FIELDS: &[&str] = &[
+    "title",
+    "author",
+    "authors",
+    "series",
+    "series_index",
+    "year",
+    "language",
+    "format",
+    "ext",
+    "name",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    name: String,
+    /// Item of list field, `authors[1]`
+    index: usize,
+    /// Part of author, `first` or `last`
+    member: Option<String>,
+    /// Width of number padded by zeros, `{series_index:02}`
+    width: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// Layout of destination, missing values are skipped with separators around
+/// them and folders left empty
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+fn parse_field(spec: &str) -> Result<Field, String> {
+    let (expr, format) = match spec.find(':') {
+        Some(colon) => (&spec[..colon], Some(&spec[colon + 1..])),
+        None => (spec, None),
+    };
+
+    let (expr, member) = match expr.find('.') {
+        Some(dot) => (&expr[..dot], Some(expr[dot + 1..].to_string())),
+        None => (expr, None),
+    };
+
+    let (name, index) = match expr.find('[') {
+        Some(open) => {
+            let index = expr[open + 1..]
+                .strip_suffix(']')
+                .and_then(|i| i.parse().ok())
+                .ok_or_else(|| format!("wrong index of field: {}", spec))?;
+            (&expr[..open], index)
+        }
+        None => (expr, 0),
+    };
+
+    if !FIELDS.contains(&name) {
+        return Err(format!("unknown field: {}", name));
+    }
+    match member.as_deref() {
+        None | Some("first") | Some("last") if name == "author" || name == "authors" => {}
+        None => {}
+        Some(member) => return Err(format!("unknown member: {} of field: {}", member, name)),
+    }
+
+    let width = match format {
+        Some(format) => Some(
+            format
+                .parse()
+                .map_err(|_| format!("wrong format of field: {}", spec))?,
+        ),
+        None => None,
+    };
+
+    Ok(Field {
+        name: name.to_string(),
+        index,
+        member,
+        width,
+    })
+}
+
+/// Trim separators left around skipped values
+fn trim_segment(segment: &str) -> &str {
+    segment
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '-' || c == '_' || c == '.')
+        .trim_end_matches(|c: char| c.is_whitespace() || c == '-' || c == '_' || c == '.')
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed field in template: {}", template))?;
+            parts.push(Part::Field(
+                parse_field(&rest[open + 1..open + close]).map_err(|e| {
+                    format!("fail to parse template: {} with error: {}", template, e)
+                })?,
+            ));
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        Ok(Template { parts })
+    }
+
+    fn value(field: &Field, metadata: &Metadata, path: &Path) -> Option<String> {
+        let number = |n: Option<u32>| {
+            n.map(|n| match field.width {
+                Some(width) => format!("{:0width$}", n, width = width),
+                None => n.to_string(),
+            })
+        };
+        let stem = || path.file_stem().map(|s| s.to_string_lossy().to_string());
+
+        match field.name.as_str() {
+            "title" => metadata.title.clone().or_else(stem),
+            "author" | "authors" => {
+                let author = metadata.authors.get(field.index)?;
+                match field.member.as_deref() {
+                    Some("first") => Some(author.first.clone()),
+                    Some("last") => Some(author.last.clone()),
+                    _ => Some(author.to_string()),
+                }
+            }
+            "series" => metadata.series.clone(),
+            "series_index" => number(metadata.series_index),
+            "year" => number(metadata.year),
+            "language" => metadata.language.clone(),
+            "format" => metadata.format.clone(),
+            "ext" => path.extension().map(|e| e.to_string_lossy().to_string()),
+            "name" => stem(),
+            _ => None,
+        }
+        .filter(|v| !v.trim().is_empty())
+    }
+
+    /// Path of book relative to destination root by metadata of book, empty
+    /// if template gives no file name for book
+    pub fn render(&self, book: &Book) -> PathBuf {
+        self.render_metadata(&book.metadata(), book.get_path())
+    }
+
+    fn render_metadata(&self, metadata: &Metadata, path: &Path) -> PathBuf {
+        let rendered = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => Template::value(field, metadata, path)
+                    .map(|v| path_safe(v.trim()))
+                    .unwrap_or_default(),
+            })
+            .collect::<String>();
+
+        rendered
+            .split('/')
+            .map(trim_segment)
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert!(
+            Template::parse("{authors[0].last}/{series}/{series_index:02} - {title}.{ext}").is_ok()
+        );
+        assert!(Template::parse("{unknown}").is_err());
+        assert!(Template::parse("{title").is_err());
+        assert!(Template::parse("{series.last}").is_err());
+        assert!(Template::parse("{series_index:x}").is_err());
+    }
+
+    #[test]
+    fn render() {
+        let template =
+            Template::parse("{authors[0].last}/{series}/{series_index:02} - {title}.{ext}")
+                .unwrap();
+
+        assert_eq!(
+            template.render(&Book::from(PathBuf::from("tests/metadata/hobbit.fb2"))),
+            PathBuf::from("Tolkien/Middle-earth/01 - The Hobbit.fb2")
+        );
+        assert_eq!(
+            template.render(&Book::from(PathBuf::from("tests/iterate/file_one.txt"))),
+            PathBuf::from("file_one.txt")
+        );
+
+        let template = Template::parse("{language}/{author} ({year})").unwrap();
+        assert_eq!(
+            template.render(&Book::from(PathBuf::from("tests/metadata/hobbit.fb2"))),
+            PathBuf::from("en/John Ronald Reuel Tolkien (1937)")
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Trash entity
+//!
+//! Recoverable storage for books deleted by synchronization
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use walkdir::WalkDir;
+
+use crate::book::Book;
+use crate::updater::{BookStatus, BookTransferStatus};
+use crate::utility::create_dir_for_path;
+
+/// Trash folder name, created in root of each side
+pub const TRASH_DIR: &str = ".ebsync-trash";
+
+/// Retention period of trashed books if not configured
+pub const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+pub struct Trash {
+    root: PathBuf,
+}
+
+impl Trash {
+    pub fn new(root: PathBuf) -> Self {
+        Trash { root }
+    }
+
+    pub fn get_path(&self) -> PathBuf {
+        self.root.join(TRASH_DIR)
+    }
+
+    /// Name of trash batch for current time: seconds since unix epoch
+    pub fn stamp() -> String {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string()
+    }
+
+    /// Path of book in batch of trash keeping its path relative to root
+    pub fn path_for(&self, stamp: &str, book: &Book) -> io::Result<PathBuf> {
+        let relative = book
+            .get_path()
+            .strip_prefix(&self.root)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+        let mut trash_path = self.get_path();
+        trash_path.push(stamp);
+        trash_path.push(relative);
+
+        Ok(trash_path)
+    }
+
+    /// Move book to batch of trash
+    pub fn put(&self, stamp: &str, book: &Book) -> io::Result<PathBuf> {
+        let trash_path = self.path_for(stamp, book)?;
+
+        create_dir_for_path(&trash_path)?;
+        fs::rename(book.get_path(), &trash_path)?;
+
+        Ok(trash_path)
+    }
+
+    /// Stored batches, oldest first
+    pub fn batches(&self) -> io::Result<Vec<String>> {
+        let trash_path = self.get_path();
+
+        if !trash_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut batches = fs::read_dir(trash_path)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect::<Vec<String>>();
+        batches.sort_by_key(|b| batch_time(b));
+
+        Ok(batches)
+    }
+
+    /// Paths of books in batch relative to root, sorted
+    pub fn books(&self, stamp: &str) -> Vec<PathBuf> {
+        let batch_path = self.get_path().join(stamp);
+
+        let mut books = WalkDir::new(&batch_path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                e.path()
+                    .strip_prefix(&batch_path)
+                    .ok()
+                    .map(|p| p.to_path_buf())
+            })
+            .collect::<Vec<PathBuf>>();
+        books.sort();
+
+        books
+    }
+
+    /// Remove batches older than retention period, all batches if none
+    pub fn empty(&self, retention: Option<Duration>) -> io::Result<Vec<String>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let expired = self
+            .batches()?
+            .into_iter()
+            .filter(|b| match retention {
+                Some(retention) => batch_time(b) + retention.as_secs() <= now,
+                None => true,
+            })
+            .collect::<Vec<String>>();
+
+        for batch in expired.iter() {
+            fs::remove_dir_all(self.get_path().join(batch))?;
+        }
+
+        if self.batches()?.is_empty() && self.get_path().exists() {
+            fs::remove_dir(self.get_path())?;
+        }
+
+        Ok(expired)
+    }
+
+    /// Move books of batch back to their original paths
+    ///
+    /// Books whose original path is occupied are left in trash
+    pub fn restore(&self, stamp: &str) -> Vec<BookStatus> {
+        let batch_path = self.get_path().join(stamp);
+
+        let results = WalkDir::new(&batch_path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| {
+                let book = Book::from(e.path().to_path_buf());
+                let dest_path = self.root.join(e.path().strip_prefix(&batch_path).unwrap());
+
+                let restored =
+                    BookStatus::new(&book, dest_path.clone(), BookTransferStatus::Restored);
+
+                let result = if dest_path.exists() {
+                    Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "destination already exists",
+                    ))
+                } else {
+                    create_dir_for_path(&dest_path)
+                        .and_then(|_| fs::rename(book.get_path(), &dest_path))
+                };
+
+                match result {
+                    Ok(_) => restored,
+                    Err(e) => restored.with_error(&e),
+                }
+            })
+            .collect::<Vec<BookStatus>>();
+
+        if results
+            .iter()
+            .all(|r| r.get_status() == &BookTransferStatus::Restored)
+        {
+            let _ = fs::remove_dir_all(&batch_path);
+            let _ = fs::remove_dir(self.get_path());
+        }
+
+        results
+    }
+}
+
+fn batch_time(batch: &str) -> u64 {
+    batch.parse().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn put_and_empty() {
+        let trash = Trash::new(PathBuf::from("tests/trash"));
+        let book = Book::from(PathBuf::from("tests/trash/file_one.txt"));
+
+        let trash_path = trash.put("1000", &book).unwrap();
+        assert_eq!(
+            trash_path,
+            PathBuf::from("tests/trash/.ebsync-trash/1000/file_one.txt")
+        );
+        assert!(!book.exists());
+        assert_eq!(trash.batches().unwrap(), [String::from("1000")]);
+        assert_eq!(trash.books("1000"), [PathBuf::from("file_one.txt")]);
+
+        fs::copy(&trash_path, book.get_path()).unwrap();
+
+        let retention = Duration::from_secs(DEFAULT_RETENTION_DAYS * 24 * 60 * 60);
+        assert_eq!(
+            trash.empty(Some(retention)).unwrap(),
+            [String::from("1000")]
+        );
+        assert!(!trash.get_path().exists());
+    }
+}
@@ -2,31 +2,89 @@
 //!
 //! Update remote and local library
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
 
+use log::{debug, trace, warn};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::book::Book;
 use crate::bookshelf::Bookshelf;
-use crate::indexer::Indexer;
-use crate::utility::create_dir_for_path;
+use crate::cache::IndexCache;
+use crate::compare::{self, Comparison};
+use crate::direction::Directions;
+use crate::filter::Filter;
+use crate::guard::Guard;
+use crate::inbox::Inbox;
+use crate::indexer::{IndexReport, Indexer, Links, WalkOptions};
+use crate::naming::Naming;
+use crate::progress::{self, Observer, Tracker};
+use crate::state::{SyncState, STATE_FILE};
+use crate::template::Template;
+use crate::trash::Trash;
+use crate::utility::{create_dir_for_path, error_kind_name, file_hash};
+
+/// Transfers running at once by default, safe for slow flash devices
+pub const DEFAULT_CONCURRENCY: usize = 1;
 
 pub struct Updater {
     local: PathBuf,
     foreign: PathBuf,
+    filter: Filter,
+    directions: Option<Directions>,
+    inbox: Option<Inbox>,
+    template: Option<Template>,
+    naming: Naming,
+    /// Books copied to inbox by this updater, stored with sync state
+    inboxed: RefCell<BTreeMap<PathBuf, PathBuf>>,
+    /// Books placed by template by this updater, stored with sync state
+    placed: RefCell<BTreeMap<PathBuf, PathBuf>>,
+    local_index: RefCell<Option<Bookshelf>>,
+    /// Errors of index given by `with_local_index`
+    local_report: IndexReport,
+    /// Errors of last scan of both sides
+    index_report: RefCell<IndexReport>,
+    /// Errors of last scan of foreign side
+    foreign_report: RefCell<IndexReport>,
+    observer: Option<Box<dyn Observer>>,
+    /// Number of transfers running at once
+    concurrency: usize,
+    /// Dir of index caches, sides are indexed from scratch if not set
+    cache: Option<PathBuf>,
+    local_links: Links,
+    foreign_links: Links,
+    options: WalkOptions,
+    hashes: Hashes,
 }
 
+#[derive(Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
 pub enum Update {
     OnlyFromLocal,
     OnlyFromLocalSync,
+    /// Move books of foreign side missing on local side to foreign trash
+    OnlyFromLocalMirror,
     OnlyFromForeign,
     OnlyFromForeignSync,
     Bidirectional,
+    Deletion,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum BookTransferStatus {
     Copied,
     Moved,
+    Renamed,
+    Trashed,
+    Restored,
     Error(String),
 }
 
@@ -38,21 +96,54 @@ impl fmt::Display for BookTransferStatus {
             match self {
                 BookTransferStatus::Copied => String::from("Copied"),
                 BookTransferStatus::Moved => String::from("Moved"),
+                BookTransferStatus::Renamed => String::from("Renamed"),
+                BookTransferStatus::Trashed => String::from("Trashed"),
+                BookTransferStatus::Restored => String::from("Restored"),
                 BookTransferStatus::Error(e) => format!("Move error: {}", e),
             }
         )
     }
 }
 
+/// Transfer of book: planned when returned by `Updater::plan`, done when
+/// returned by `Updater::update`
 #[derive(Debug, PartialEq, Clone)]
 pub struct BookStatus {
     name: String,
     src: PathBuf,
     dst: PathBuf,
     status: BookTransferStatus,
+    /// Size of book, unknown if file can't be read
+    bytes: Option<u64>,
+    /// Kind of error of failed transfer, like `not_found`
+    error_kind: Option<String>,
 }
 
 impl BookStatus {
+    pub(crate) fn new(book: &Book, dst: PathBuf, status: BookTransferStatus) -> Self {
+        BookStatus {
+            name: book.get_name().to_string(),
+            src: book.get_path().to_path_buf(),
+            dst,
+            status,
+            bytes: fs::metadata(book.get_path()).ok().map(|m| m.len()),
+            error_kind: None,
+        }
+    }
+
+    /// Transfer of book known by other name on other side, like moved book
+    fn named(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Failed transfer with error status
+    pub(crate) fn with_error(mut self, e: &io::Error) -> Self {
+        self.status = BookTransferStatus::Error(e.to_string());
+        self.error_kind = Some(error_kind_name(e.kind()));
+        self
+    }
+
     pub fn get_name(&self) -> &String {
         &self.name
     }
@@ -68,131 +159,946 @@ impl BookStatus {
     pub fn get_status(&self) -> &BookTransferStatus {
         &self.status
     }
+
+    pub fn get_bytes(&self) -> Option<u64> {
+        self.bytes
+    }
+
+    pub fn get_error_kind(&self) -> Option<&String> {
+        self.error_kind.as_ref()
+    }
+}
+
+fn cross_diff(local: &Bookshelf, foreign: &Bookshelf) -> (Bookshelf, Bookshelf) {
+    (local.difference(foreign), foreign.difference(local))
+}
+
+/// Do planned transfer, failed transfer gets error status
+fn transfer(mut book_status: BookStatus, tracker: Option<&Tracker>) -> BookStatus {
+    let (src, dst) = (&book_status.src, &book_status.dst);
+    let mut copied = 0;
+
+    if let Some(tracker) = tracker {
+        tracker.start(&book_status);
+    }
+
+    let result = match book_status.status {
+        BookTransferStatus::Copied => create_dir_for_path(dst).and_then(|_| match tracker {
+            Some(tracker) => {
+                let (bytes, result) = progress::copy(src, dst, tracker);
+                copied = bytes;
+                result.map(Some)
+            }
+            None => fs::copy(src, dst).map(Some),
+        }),
+        BookTransferStatus::Moved | BookTransferStatus::Renamed | BookTransferStatus::Trashed => {
+            create_dir_for_path(dst).and_then(|_| fs::rename(src, dst).map(|_| None))
+        }
+        _ => Ok(None),
+    };
+
+    if let Some(tracker) = tracker {
+        tracker.done(&book_status, copied);
+    }
+
+    match result {
+        Ok(bytes) => {
+            debug!(
+                "{} {} -> {}",
+                book_status.status,
+                src.display(),
+                dst.display()
+            );
+            if let Some(bytes) = bytes {
+                book_status.bytes = Some(bytes);
+            }
+            book_status
+        }
+        Err(e) => {
+            warn!(
+                "Fail to transfer {} -> {}: {}",
+                src.display(),
+                dst.display(),
+                e
+            );
+            book_status.with_error(&e)
+        }
+    }
 }
 
-fn cross_diff((local, foreign): (Bookshelf, Bookshelf)) -> (Bookshelf, Bookshelf) {
-    (local.difference(&foreign), foreign.difference(&local))
+/// Copies by bounded pool of workers, results keep order of plan
+///
+/// Moves, renames and deletions can depend on each other, they run one by
+/// one in order of plan before copies
+fn transfer_parallel(
+    plan: Vec<BookStatus>,
+    tracker: Option<&Tracker>,
+    workers: usize,
+) -> Vec<BookStatus> {
+    let total = plan.len();
+    let (copies, others): (Vec<_>, Vec<_>) = plan
+        .into_iter()
+        .enumerate()
+        .partition(|(_, b)| b.get_status() == &BookTransferStatus::Copied);
+
+    let mut done = others
+        .into_iter()
+        .map(|(index, book_status)| (index, transfer(book_status, tracker)))
+        .collect::<Vec<_>>();
+
+    let queue = Mutex::new(copies.into_iter());
+    let results = Mutex::new(Vec::with_capacity(total));
+
+    thread::scope(|scope| {
+        for _ in 0..workers.min(total) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((index, book_status)) => {
+                        let result = transfer(book_status, tracker);
+                        results.lock().unwrap().push((index, result));
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+
+    done.append(&mut results.into_inner().unwrap());
+    done.sort_by_key(|(index, _)| *index);
+    done.into_iter().map(|(_, result)| result).collect()
 }
 
-fn cross_inter((local, foreign): (Bookshelf, Bookshelf)) -> (Bookshelf, Bookshelf) {
-    (local.intersection(&foreign), foreign.intersection(&local))
+fn execute(
+    plan: Vec<BookStatus>,
+    observer: Option<&dyn Observer>,
+    concurrency: usize,
+) -> Vec<BookStatus> {
+    let tracker = observer.map(|observer| Tracker::new(observer, &plan));
+
+    let results = if concurrency > 1 {
+        transfer_parallel(plan, tracker.as_ref(), concurrency)
+    } else {
+        plan.into_iter()
+            .map(|book_status| transfer(book_status, tracker.as_ref()))
+            .collect()
+    };
+
+    if let Some(tracker) = tracker {
+        tracker.finish();
+    }
+    results
 }
 
-fn copy_files(books: Bookshelf, destination: &PathBuf) -> Vec<BookStatus> {
+/// Keep index of side up to date with done transfers
+fn track(index: &mut Bookshelf, results: &[BookStatus]) {
+    let root = index.get_path().to_path_buf();
+
+    for book_status in results.iter() {
+        let src = book_status.get_src();
+        let dst = book_status.get_dst();
+
+        match book_status.get_status() {
+            BookTransferStatus::Copied | BookTransferStatus::Restored => {
+                if dst.starts_with(&root) {
+                    index.add(Book::from(dst.to_path_buf()));
+                }
+            }
+            BookTransferStatus::Moved | BookTransferStatus::Renamed => {
+                if src.starts_with(&root) {
+                    index.remove(&Book::from(src.to_path_buf()));
+                    index.add(Book::from(dst.to_path_buf()));
+                }
+            }
+            BookTransferStatus::Trashed => {
+                if src.starts_with(&root) {
+                    index.remove(&Book::from(src.to_path_buf()));
+                }
+            }
+            BookTransferStatus::Error(_) => (),
+        }
+    }
+}
+
+/// Plan copying books to local inbox, placed by inbox filing
+///
+/// Book filed to path of other planned book or of existing file isn't
+/// copied, it gets error
+fn plan_inbox(books: &Bookshelf, local: &Path, inbox: &Inbox) -> Vec<BookStatus> {
+    let mut planned = BTreeSet::new();
+
     books
         .iter()
         .map(|b| {
-            let mut dest_path = destination.to_path_buf();
-            dest_path.push(b.get_path().strip_prefix(books.get_path()).unwrap());
-
-            let status = create_dir_for_path(&dest_path);
-
-            BookStatus {
-                name: b.get_name().to_string(),
-                src: b.get_path().to_path_buf(),
-                dst: dest_path.to_path_buf(),
-                status: if let Ok(_) = status {
-                    match fs::copy(b.get_path(), dest_path) {
-                        Err(e) => BookTransferStatus::Error(e.to_string()),
-                        Ok(_) => BookTransferStatus::Copied,
-                    }
-                } else {
-                    BookTransferStatus::Error(status.err().unwrap().to_string())
-                },
+            let dst = local.join(inbox.path_for(b));
+            let book_status = BookStatus::new(b, dst.clone(), BookTransferStatus::Copied);
+            if dst.exists() || !planned.insert(dst) {
+                book_status.with_error(&io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "path of inbox is taken by other book",
+                ))
+            } else {
+                book_status
             }
         })
         .collect()
 }
 
-fn move_files(books_src: Bookshelf, books_dst: Bookshelf) -> Vec<BookStatus> {
-    books_src
+/// Plan copying books to foreign side, placed by path template
+///
+/// Book rendered to path of other planned book or of existing file isn't
+/// copied, it gets error
+fn plan_template(books: &Bookshelf, foreign: &Path, template: &Template) -> Vec<BookStatus> {
+    let mut planned = BTreeSet::new();
+
+    books
+        .iter()
+        .map(|b| {
+            let mut path = template.render(b);
+            if path.as_os_str().is_empty() {
+                path = books.relative_path(b).to_path_buf();
+            }
+
+            let dst = foreign.join(path);
+            let book_status = BookStatus::new(b, dst.clone(), BookTransferStatus::Copied);
+            if dst.exists() || !planned.insert(dst) {
+                book_status.with_error(&io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "path of template is taken by other book",
+                ))
+            } else {
+                book_status
+            }
+        })
+        .collect()
+}
+
+fn plan_copy(books: &Bookshelf, destination: &Path) -> Vec<BookStatus> {
+    books
         .iter()
-        .zip(books_dst.iter())
-        .filter(|books_is_equal| {
-            let (book_src, book_dst) = books_is_equal;
+        .map(|b| {
+            let mut dest_path = destination.to_path_buf();
+            dest_path.push(books.relative_path(b));
 
-            book_src.get_name() == book_dst.get_name()
+            BookStatus::new(b, dest_path, BookTransferStatus::Copied)
         })
-        .filter(|books_to_allowed| {
-            let (book_src, book_dst) = books_to_allowed;
+        .collect()
+}
+
+type BookPair<'a> = (&'a Book, &'a Book, BookTransferStatus);
 
-            book_src.get_path().strip_prefix(books_src.get_path())
-                != book_dst.get_path().strip_prefix(books_dst.get_path())
+/// Pair books with equal names but different relative paths (directory moves)
+fn pair_by_name<'a>(books_src: &'a Bookshelf, books_dst: &'a Bookshelf) -> Vec<BookPair<'a>> {
+    books_src
+        .iter()
+        .filter_map(|book_src| books_dst.get(book_src).map(|book_dst| (book_src, book_dst)))
+        .filter(|(book_src, book_dst)| {
+            books_src.relative_path(book_src) != books_dst.relative_path(book_dst)
         })
-        .map(|books_to_move| {
-            let (book_src, book_dst) = books_to_move;
+        .map(|(book_src, book_dst)| (book_src, book_dst, BookTransferStatus::Moved))
+        .collect()
+}
+
+/// Hashes of books computed by plan, remembered by index cache
+type Hashes = Mutex<BTreeMap<PathBuf, u64>>;
+
+fn content_index<'a>(
+    books: &'a Bookshelf,
+    sizes: &BTreeSet<u64>,
+    hashes: &Hashes,
+) -> BTreeMap<(u64, u64), Vec<&'a Book>> {
+    // Books are hashed in parallel, index is filled in order of books
+    books
+        .iter()
+        .collect::<Vec<&Book>>()
+        .par_iter()
+        .filter_map(|book| {
+            let size = fs::metadata(book.get_path()).ok()?.len();
+            if !sizes.contains(&size) {
+                return None;
+            }
+            let hash = match book.get_hash() {
+                Some(hash) => hash,
+                None => {
+                    let hash = file_hash(book.get_path()).ok()?;
+                    let path = book.get_path().to_path_buf();
+                    hashes.lock().unwrap().insert(path, hash);
+                    hash
+                }
+            };
+            Some(((size, hash), *book))
+        })
+        .collect::<Vec<((u64, u64), &Book)>>()
+        .into_iter()
+        .fold(BTreeMap::new(), |mut index, (key, book)| {
+            index.entry(key).or_insert_with(Vec::new).push(book);
+            index
+        })
+}
+
+fn content_sizes(books: &Bookshelf) -> BTreeSet<u64> {
+    books
+        .iter()
+        .filter_map(|book| fs::metadata(book.get_path()).ok())
+        .map(|metadata| metadata.len())
+        .collect()
+}
+
+/// Pair books present only on one side by equal content (renames)
+///
+/// Ambiguous content (several books with the same size and hash on either
+/// side) is never paired
+fn pair_by_content<'a>(
+    only_src: &'a Bookshelf,
+    only_dst: &'a Bookshelf,
+    hashes: &Hashes,
+) -> Vec<BookPair<'a>> {
+    let sizes: BTreeSet<u64> = content_sizes(only_src)
+        .intersection(&content_sizes(only_dst))
+        .cloned()
+        .collect();
+
+    if sizes.is_empty() {
+        return Vec::new();
+    }
+
+    let index_dst = content_index(only_dst, &sizes, hashes);
+
+    content_index(only_src, &sizes, hashes)
+        .into_iter()
+        .filter_map(
+            |(key, books_src)| match (books_src.as_slice(), index_dst.get(&key)) {
+                ([book_src], Some(books_dst)) if books_dst.len() == 1 => {
+                    Some((*book_src, books_dst[0], BookTransferStatus::Renamed))
+                }
+                _ => None,
+            },
+        )
+        .collect()
+}
 
+/// Plan relocation of books of destination to match layout of source
+///
+/// Books are paired by identity: by name when it exists on both sides
+/// (directory move) and by content when it exists only on one side (rename)
+fn plan_move(
+    books_src: &Bookshelf,
+    books_dst: &Bookshelf,
+    links: Links,
+    hashes: &Hashes,
+) -> Vec<BookStatus> {
+    let only_src = books_src.difference(books_dst);
+    let only_dst = books_dst.difference(books_src);
+
+    let mut pairs = pair_by_name(books_src, books_dst);
+    pairs.append(&mut pair_by_content(&only_src, &only_dst, hashes));
+
+    let root = books_dst.get_path();
+    let canonical = root.canonicalize().ok();
+    pairs
+        .into_iter()
+        .map(|(book_src, book_dst, moved)| {
             let mut dest_path = books_dst.get_path().to_path_buf();
-            dest_path.push(
-                book_src
-                    .get_path()
-                    .strip_prefix(books_src.get_path())
-                    .unwrap(),
-            );
+            dest_path.push(books_src.relative_path(book_src));
 
-            let status = create_dir_for_path(&dest_path);
-
-            BookStatus {
-                name: book_src.get_name().to_string(),
-                src: book_dst.get_path().to_path_buf(),
-                dst: dest_path.to_path_buf(),
-                status: {
-                    if let Ok(_) = status {
-                        match fs::rename(book_dst.get_path(), dest_path) {
-                            Err(e) => BookTransferStatus::Error(e.to_string()),
-                            Ok(_) => BookTransferStatus::Moved,
-                        }
-                    } else {
-                        BookTransferStatus::Error(status.err().unwrap().to_string())
-                    }
-                },
+            let book_status = BookStatus::new(book_dst, dest_path, moved);
+            let book_status = book_status.named(book_src.get_name());
+            movable(book_status, root, canonical.as_deref(), links)
+        })
+        .collect()
+}
+
+/// Plan moving books to trash of their side
+fn plan_trash(books: &Bookshelf, links: Links, stamp: &str) -> Vec<BookStatus> {
+    let root = books.get_path();
+    let trash = Trash::new(root.to_path_buf());
+    let canonical = root.canonicalize().ok();
+
+    books
+        .iter()
+        .map(|b| match trash.path_for(stamp, b) {
+            Ok(trash_path) => movable(
+                BookStatus::new(b, trash_path, BookTransferStatus::Trashed),
+                root,
+                canonical.as_deref(),
+                links,
+            ),
+            Err(e) => {
+                BookStatus::new(b, PathBuf::new(), BookTransferStatus::Trashed).with_error(&e)
             }
         })
         .collect()
 }
 
+/// Book reached through link of side root, link itself or linked folder
+fn linked(path: &Path, root: &Path) -> bool {
+    path.strip_prefix(root)
+        .map(|relative| {
+            relative
+                .ancestors()
+                .filter(|part| !part.as_os_str().is_empty())
+                .any(|part| {
+                    root.join(part)
+                        .symlink_metadata()
+                        .map(|m| m.file_type().is_symlink())
+                        .unwrap_or(false)
+                })
+        })
+        .unwrap_or(false)
+}
+
+/// Book which can be moved inside its side
+///
+/// Linked books of `Copy` policy are only copied, book of folder linked from
+/// outside of canonical root would be taken out of linked folder or fail
+/// across filesystems
+fn movable(
+    book_status: BookStatus,
+    root: &Path,
+    canonical: Option<&Path>,
+    links: Links,
+) -> BookStatus {
+    if links == Links::Copy && linked(book_status.get_src(), root) {
+        return book_status.with_error(&io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "book is linked, links of copy policy are only copied",
+        ));
+    }
+
+    let folder = book_status
+        .get_src()
+        .parent()
+        .and_then(|folder| folder.canonicalize().ok());
+
+    match (folder, canonical) {
+        (Some(folder), Some(root)) if !folder.starts_with(root) => {
+            book_status.with_error(&io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("book is linked from outside of root: {}", folder.display()),
+            ))
+        }
+        _ => book_status,
+    }
+}
+
 impl Updater {
     pub fn new(local: PathBuf, foreign: PathBuf) -> Self {
-        Updater { local, foreign }
+        Updater {
+            local,
+            foreign,
+            filter: Filter::default(),
+            directions: None,
+            inbox: None,
+            template: None,
+            naming: Naming::default(),
+            inboxed: RefCell::new(BTreeMap::new()),
+            placed: RefCell::new(BTreeMap::new()),
+            local_index: RefCell::new(None),
+            local_report: IndexReport::new(),
+            index_report: RefCell::new(IndexReport::new()),
+            foreign_report: RefCell::new(IndexReport::new()),
+            observer: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            cache: None,
+            local_links: Links::default(),
+            foreign_links: Links::default(),
+            options: WalkOptions::default(),
+            hashes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Use already indexed local side instead of indexing it on every scan
+    ///
+    /// Index is kept up to date with transfers done by updater, so it can be
+    /// taken by `into_local_index` and reused for next destination
+    pub fn with_local_index(self, index: Bookshelf) -> Self {
+        self.local_index.replace(Some(index));
+        self
     }
 
-    fn scan_area(&self) -> (Bookshelf, Bookshelf) {
+    /// Errors of index given by `with_local_index`, they are reported by
+    /// every scan of updater
+    pub fn with_local_report(mut self, report: IndexReport) -> Self {
+        self.local_report = report;
+        self
+    }
+
+    pub fn into_local_index(self) -> Option<Bookshelf> {
+        self.local_index.into_inner()
+    }
+
+    /// Synchronize only part of both sides selected by filter
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Synchronize subtrees of both sides by their own directions, every
+    /// update is applied only to subtrees with direction requiring it
+    pub fn with_directions(mut self, directions: Directions) -> Self {
+        self.directions = Some(directions);
+        self
+    }
+
+    /// Copy books found only on foreign side to local inbox instead of their
+    /// foreign relative path
+    pub fn with_inbox(mut self, inbox: Inbox) -> Self {
+        self.inbox = Some(inbox);
+        self
+    }
+
+    /// Place books copied to foreign side by path template instead of their
+    /// local relative path
+    pub fn with_template(mut self, template: Template) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Fill metadata of books on both sides from their names
+    pub fn with_naming(mut self, naming: Naming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Run up to `concurrency` transfers at once, results keep order of plan
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Report progress of transfers of every update to observer
+    pub fn with_observer(mut self, observer: Box<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Keep indexes of sides in cache of dir, next scans read only changed
+    /// directories and files
+    pub fn with_cache(mut self, dir: PathBuf) -> Self {
+        self.cache = Some(dir);
+        self
+    }
+
+    /// Index symbolic links of each side by own policy
+    pub fn with_links(mut self, local: Links, foreign: Links) -> Self {
+        self.local_links = local;
+        self.foreign_links = foreign;
+        self
+    }
+
+    /// Walk sides by options: hidden entries, max depth, device system folders
+    pub fn with_walk_options(mut self, options: WalkOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Indexer of side using cache given by `with_cache`
+    fn indexer(&self, path: &Path, links: Links) -> Indexer {
+        let indexer = Indexer::new(path.to_path_buf())
+            .with_naming(self.naming.clone())
+            .with_links(links)
+            .with_options(self.options.clone());
+        match &self.cache {
+            Some(dir) => indexer.with_cache(dir.clone()),
+            None => indexer,
+        }
+    }
+
+    pub fn get_local(&self) -> &PathBuf {
+        &self.local
+    }
+
+    pub fn get_foreign(&self) -> &PathBuf {
+        &self.foreign
+    }
+
+    /// Entries of both sides missing from index of last scan
+    pub fn index_report(&self) -> IndexReport {
+        self.index_report.borrow().clone()
+    }
+
+    /// Entries of foreign side missing from index of last scan, local
+    /// entries given by `with_local_report` are reported by its owner
+    pub fn foreign_report(&self) -> IndexReport {
+        self.foreign_report.borrow().clone()
+    }
+
+    /// Indexed sides selected by filter, paired foreign books are known by
+    /// names of their local books
+    pub fn scan_area(&self) -> (Bookshelf, Bookshelf) {
+        self.with_sides(|local, foreign| (local.clone(), self.selected(foreign).into_owned()))
+    }
+
+    /// Call function with indexed sides, local side is selected by filter,
+    /// foreign side is whole, index given by `with_local_index` is borrowed
+    pub fn with_sides<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&Bookshelf, &Bookshelf) -> T,
+    {
+        let foreign = self.indexer(&self.foreign, self.foreign_links);
+        let index = self.local_index.borrow();
+
+        // Sides are indexed at once, foreign by own thread
+        let ((local, mut report), (foreign, foreign_report)) = thread::scope(|scope| {
+            let foreign = scope.spawn(move || foreign.scan());
+            let local = match index.as_ref() {
+                Some(index) => (Cow::Borrowed(index), self.local_report.clone()),
+                None => {
+                    let (index, report) = self.indexer(&self.local, self.local_links).scan();
+                    (Cow::Owned(index), report)
+                }
+            };
+            (local, foreign.join().unwrap())
+        });
+        report.merge(foreign_report.clone());
+        self.index_report.replace(report);
+        self.foreign_report.replace(foreign_report);
+
+        f(&self.filter.select(&local), &self.identify(foreign))
+    }
+
+    fn identify(&self, foreign: Bookshelf) -> Bookshelf {
+        let paired = self.paired();
+        if paired.is_empty() {
+            return foreign;
+        }
+
+        let mut identified = Bookshelf::from(foreign.get_path().to_path_buf());
+        for book in foreign.iter() {
+            let name = paired
+                .get(foreign.relative_path(book))
+                .and_then(|local| local.file_name())
+                .map(|name| Book::from(PathBuf::from(name)).get_name().to_string());
+
+            identified.add(match name {
+                Some(name) => Book::with_name(name, book.get_path().to_path_buf()),
+                None => book.clone(),
+            });
+        }
+        identified
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.foreign.join(STATE_FILE)
+    }
+
+    /// Books synchronized by last run, empty before first synchronization
+    pub fn load_state(&self) -> Result<SyncState, String> {
+        SyncState::load(self.state_path())
+    }
+
+    /// State for plans and comparisons, unreadable state is taken as empty
+    ///
+    /// Guarded update and store of state refuse unreadable state
+    fn known_state(&self) -> SyncState {
+        self.load_state().unwrap_or_else(|e| {
+            warn!("{}", e);
+            SyncState::new(self.state_path())
+        })
+    }
+
+    /// Remember books present on both sides as synchronized
+    ///
+    /// Should be called after synchronization, it's base for `Update::Deletion`
+    pub fn store_state(&self) -> Result<(), String> {
+        // NOTE: unreadable state is left for user to fix, it isn't replaced
+        let stored = self.load_state()?;
+
+        let mut state = SyncState::new(self.state_path());
+
+        // Pairs are remembered while foreign copy exists
+        let foreign_paths = self.with_sides(|local, foreign| {
+            state.record(&local.intersection(foreign));
+            foreign
+                .iter()
+                .map(|b| foreign.relative_path(b).to_path_buf())
+                .collect::<BTreeSet<PathBuf>>()
+        });
+        for (foreign_path, local_path) in self.inboxed(&stored) {
+            if foreign_paths.contains(&foreign_path) {
+                state.add_inbox(foreign_path, local_path);
+            }
+        }
+        for (foreign_path, local_path) in self.placed(&stored) {
+            if foreign_paths.contains(&foreign_path) {
+                state.add_pair(foreign_path, local_path);
+            }
+        }
+
+        // NOTE: hashes are costly to read from device, they are kept for
+        // next plans
+        if let Some(dir) = &self.cache {
+            let hashes = self.hashes.lock().unwrap();
+            for root in [&self.local, &self.foreign].iter() {
+                if let Err(e) = IndexCache::remember(dir, root, &hashes) {
+                    warn!("Error for remember hashes: {}", e);
+                }
+            }
+        }
+
+        state.store()
+    }
+
+    /// Foreign books copied to local inbox with their local paths
+    fn inboxed(&self, state: &SyncState) -> BTreeMap<PathBuf, PathBuf> {
+        let mut inboxed = state.get_inbox().clone();
+        inboxed.extend(self.inboxed.borrow().clone());
+        inboxed
+    }
+
+    /// Foreign paths of local books placed by template with their local paths
+    fn placed(&self, state: &SyncState) -> BTreeMap<PathBuf, PathBuf> {
+        let mut placed = state.get_pairs().clone();
+        placed.extend(self.placed.borrow().clone());
+        placed
+    }
+
+    /// Foreign paths of books placed by different paths on sides with their
+    /// local paths
+    fn paired(&self) -> BTreeMap<PathBuf, PathBuf> {
+        let state = self.known_state();
+        let mut paired = self.inboxed(&state);
+        paired.extend(self.placed(&state));
+        paired
+    }
+
+    /// Foreign books selected by filter, paired books are selected by paths
+    /// of their local books
+    fn selected<'a>(&self, foreign: &'a Bookshelf) -> Cow<'a, Bookshelf> {
+        if self.filter.is_empty() {
+            return Cow::Borrowed(foreign);
+        }
+
+        let paired = self.paired();
+        self.filter.select_by(foreign, |b| {
+            let relative = foreign.relative_path(b);
+            paired
+                .get(relative)
+                .cloned()
+                .unwrap_or_else(|| relative.to_path_buf())
+        })
+    }
+
+    /// Sides without paired books, their layouts differ on purpose
+    fn without_paired<'a>(
+        &self,
+        local: &'a Bookshelf,
+        foreign: &'a Bookshelf,
+    ) -> (Cow<'a, Bookshelf>, Cow<'a, Bookshelf>) {
+        let paired = self.paired();
+        if paired.is_empty() {
+            return (Cow::Borrowed(local), Cow::Borrowed(foreign));
+        }
+
+        let local_paths = paired.values().collect::<BTreeSet<&PathBuf>>();
         (
-            Indexer::new(self.local.clone()).index(),
-            Indexer::new(self.foreign.clone()).index(),
+            Cow::Owned(
+                local.filter(|b| !local_paths.contains(&local.relative_path(b).to_path_buf())),
+            ),
+            Cow::Owned(foreign.filter(|b| !paired.contains_key(foreign.relative_path(b)))),
         )
     }
 
-    pub fn update(&self, update: Update) -> Vec<BookStatus> {
+    /// Books of side in subtrees synchronized by update
+    fn allowed<'a>(&self, books: &'a Bookshelf, update: &Update) -> Cow<'a, Bookshelf> {
+        match &self.directions {
+            Some(directions) => Cow::Owned(directions.apply(books, update)),
+            None => Cow::Borrowed(books),
+        }
+    }
+
+    /// Transfers required by update for sides given by `with_sides`, nothing
+    /// is changed
+    pub fn plan_for(
+        &self,
+        update: &Update,
+        (local, whole): (&Bookshelf, &Bookshelf),
+    ) -> Vec<BookStatus> {
         use Update::*;
 
-        let (from_local, from_foreign) = match update {
-            Bidirectional | OnlyFromLocal | OnlyFromForeign => cross_diff(self.scan_area()),
-            OnlyFromLocalSync | OnlyFromForeignSync => cross_inter(self.scan_area()),
-        };
+        // NOTE: books out of selection on foreign side are left alone, but
+        // they aren't copied again
+        let foreign = &self.selected(whole);
 
         match update {
-            OnlyFromLocal => copy_files(from_local, &self.foreign),
-            OnlyFromLocalSync => move_files(from_local, from_foreign),
-            OnlyFromForeign => copy_files(from_foreign, &self.local),
-            OnlyFromForeignSync => move_files(from_foreign, from_local),
+            OnlyFromLocal => {
+                let from_local = self.allowed(local, update).difference(whole);
+                match &self.template {
+                    Some(template) => plan_template(&from_local, &self.foreign, template),
+                    None => plan_copy(&from_local, &self.foreign),
+                }
+            }
+            OnlyFromLocalSync => {
+                let (local, foreign) = self.without_paired(local, foreign);
+                plan_move(
+                    &self.allowed(&local, update),
+                    &self.allowed(&foreign, update),
+                    self.foreign_links,
+                    &self.hashes,
+                )
+            }
+            OnlyFromLocalMirror => plan_trash(
+                &self.allowed(foreign, update).difference(local),
+                self.foreign_links,
+                &Trash::stamp(),
+            ),
+            OnlyFromForeign => {
+                let from_foreign = self.allowed(foreign, update).difference(local);
+                match &self.inbox {
+                    Some(inbox) => plan_inbox(&from_foreign, &self.local, inbox),
+                    None => plan_copy(&from_foreign, &self.local),
+                }
+            }
+            OnlyFromForeignSync => {
+                let (local, foreign) = self.without_paired(local, foreign);
+                plan_move(
+                    &self.allowed(&foreign, update),
+                    &self.allowed(&local, update),
+                    self.local_links,
+                    &self.hashes,
+                )
+            }
             Bidirectional => {
-                let mut results_of_copy: Vec<BookStatus> = Vec::new();
-                results_of_copy.append(&mut copy_files(from_local, &self.foreign));
-                results_of_copy.append(&mut copy_files(from_foreign, &self.local));
-                results_of_copy
+                let (from_local, from_foreign) = cross_diff(local, foreign);
+
+                let mut plan_of_copy: Vec<BookStatus> = Vec::new();
+                plan_of_copy.append(&mut plan_copy(&from_local, &self.foreign));
+                plan_of_copy.append(&mut plan_copy(&from_foreign, &self.local));
+                plan_of_copy
             }
+            Deletion => {
+                // Without stored state nothing is known to be deleted
+                let state = self.known_state();
+                let stamp = Trash::stamp();
+
+                // Deletion is propagated only to subtrees accepting changes
+                // from other side
+                let deleted = |books: &Bookshelf, other: &Bookshelf| {
+                    books.difference(other).filter(|b| state.have(b))
+                };
+                let from_local = deleted(&self.allowed(local, &OnlyFromForeign), foreign);
+                let from_foreign = deleted(&self.allowed(foreign, &OnlyFromLocal), local);
+
+                let mut plan_of_trash: Vec<BookStatus> = Vec::new();
+                plan_of_trash.append(&mut plan_trash(&from_local, self.local_links, &stamp));
+                plan_of_trash.append(&mut plan_trash(&from_foreign, self.foreign_links, &stamp));
+                plan_of_trash
+            }
+        }
+    }
+
+    /// Transfers required by update, nothing is changed
+    pub fn plan(&self, update: Update) -> Vec<BookStatus> {
+        self.with_sides(|local, foreign| self.plan_for(&update, (local, foreign)))
+    }
+
+    fn log_plan(update: &Update, plan: &[BookStatus]) {
+        debug!("Planned {} transfers for {:?}", plan.len(), update);
+        for book_status in plan {
+            trace!(
+                "Plan {} {} -> {}",
+                book_status.status,
+                book_status.src.display(),
+                book_status.dst.display()
+            );
         }
     }
+
+    /// Compare sides book by book, paired books placed on purpose are same
+    pub fn compare(&self, content: bool) -> Vec<Comparison> {
+        let paired = self.paired();
+
+        self.with_sides(|local, foreign| compare::compare(local, &self.selected(foreign), content))
+            .into_iter()
+            .map(|c| match (c.get_local(), c.get_foreign()) {
+                (Some(l), Some(f)) if paired.get(f) == Some(l) => c.placed(),
+                _ => c,
+            })
+            .collect()
+    }
+
+    pub fn update(&self, update: Update) -> Vec<BookStatus> {
+        let plan = self.plan(update);
+        Updater::log_plan(&update, &plan);
+        self.execute(plan)
+    }
+
+    fn execute(&self, plan: Vec<BookStatus>) -> Vec<BookStatus> {
+        let results = execute(plan, self.observer.as_deref(), self.concurrency);
+
+        for result in results.iter() {
+            if result.get_status() != &BookTransferStatus::Copied {
+                continue;
+            }
+
+            let relative =
+                |path: &Path, root: &Path| path.strip_prefix(root).ok().map(|p| p.to_path_buf());
+
+            // Copy placed by other path than source is paired with it
+            match (
+                relative(result.get_src(), &self.local),
+                relative(result.get_dst(), &self.foreign),
+            ) {
+                (Some(local), Some(foreign)) if foreign != local => {
+                    self.placed.borrow_mut().insert(foreign, local);
+                }
+                (Some(_), Some(_)) => {}
+                _ => {
+                    if let (Some(foreign), Some(local)) = (
+                        relative(result.get_src(), &self.foreign),
+                        relative(result.get_dst(), &self.local),
+                    ) {
+                        if foreign != local {
+                            self.inboxed.borrow_mut().insert(foreign, local);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(index) = self.local_index.borrow_mut().as_mut() {
+            track(index, &results);
+        }
+
+        results
+    }
+
+    /// Update only if guard allows both sides and planned transfers
+    /// Plan update checked by guard as it would be run by `update_guarded`
+    pub fn plan_guarded(&self, update: Update, guard: &Guard) -> Result<Vec<BookStatus>, String> {
+        guard.check_paths(&self.local, &self.foreign)?;
+
+        self.with_sides(|local, foreign| {
+            guard.check_index(local, foreign, &self.load_state()?)?;
+            guard.check_report(&self.index_report.borrow())?;
+
+            let books = local.union(&self.selected(foreign)).len();
+            let plan = self.plan_for(&update, (local, foreign));
+            Updater::log_plan(&update, &plan);
+            guard.check_plan(&plan, books)?;
+            Ok(plan)
+        })
+    }
+
+    pub fn update_guarded(&self, update: Update, guard: &Guard) -> Result<Vec<BookStatus>, String> {
+        let plan = self.plan_guarded(update, guard)?;
+        Ok(self.execute(plan))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs;
     use std::path::PathBuf;
+    use std::sync::Arc;
 
     use super::*;
-    use crate::book::Book;
+    use crate::compare::Difference;
+    use crate::direction::Direction;
+    use crate::filter::{patterns, Rule};
+    use crate::inbox::Filing;
+    use crate::progress::Progress;
+    use crate::template::Template;
 
     #[test]
     fn scan_area_check() {
@@ -231,7 +1137,7 @@ mod tests {
             PathBuf::from("tests/scan_area/foreign"),
         );
 
-        let (from_local, from_foreign) = cross_diff(uper.scan_area());
+        let (from_local, from_foreign) = uper.with_sides(cross_diff);
 
         let ixer_res: Vec<_> = from_local.iter().cloned().collect();
         assert_eq!(ixer_res, [Book::new(String::from("file_three.txt")),]);
@@ -240,6 +1146,85 @@ mod tests {
         assert_eq!(ixer_res, [Book::new(String::from("file_four.txt")),]);
     }
 
+    #[test]
+    fn guarded_update_check() {
+        let uper = Updater::new(
+            PathBuf::from("tests/scan_area/local"),
+            PathBuf::from("tests/scan_area/foreign"),
+        );
+
+        let plan = uper
+            .plan(Update::Bidirectional)
+            .iter()
+            .map(|e| (e.get_name().to_string(), e.get_status().clone()))
+            .collect::<Vec<(String, BookTransferStatus)>>();
+        assert_eq!(
+            plan,
+            [
+                (String::from("file_three.txt"), BookTransferStatus::Copied),
+                (String::from("file_four.txt"), BookTransferStatus::Copied),
+            ]
+        );
+
+        assert!(uper
+            .plan_guarded(Update::Bidirectional, &Guard::new(25, false))
+            .is_err());
+        assert!(uper
+            .update_guarded(Update::Bidirectional, &Guard::new(25, false))
+            .is_err());
+        assert!(!PathBuf::from("tests/scan_area/foreign/file_three.txt").exists());
+    }
+
+    #[test]
+    fn broken_state_check() {
+        let uper = Updater::new(
+            PathBuf::from("tests/broken_state/local"),
+            PathBuf::from("tests/broken_state/foreign"),
+        );
+        let stored = fs::read_to_string(uper.state_path()).unwrap();
+
+        assert!(uper
+            .update_guarded(Update::Deletion, &Guard::new(100, true))
+            .is_err());
+        assert!(uper.store_state().is_err());
+        assert_eq!(fs::read_to_string(uper.state_path()).unwrap(), stored);
+    }
+
+    #[test]
+    fn filtered_plan_check() {
+        let uper = Updater::new(
+            PathBuf::from("tests/scan_area/local"),
+            PathBuf::from("tests/scan_area/foreign"),
+        )
+        .with_filter(Filter::default().with(Rule::ExcludePatterns(
+            patterns(&[String::from("*_three.txt")]).unwrap(),
+        )));
+
+        let plan = uper
+            .plan(Update::Bidirectional)
+            .iter()
+            .map(|e| (e.get_name().to_string(), e.get_status().clone()))
+            .collect::<Vec<(String, BookTransferStatus)>>();
+        assert_eq!(
+            plan,
+            [(String::from("file_four.txt"), BookTransferStatus::Copied),]
+        );
+    }
+
+    #[test]
+    fn selected_side_check() {
+        // Device copy lost language of book, other book of device isn't
+        // selected
+        let uper = Updater::new(
+            PathBuf::from("tests/selected_side/local"),
+            PathBuf::from("tests/selected_side/foreign"),
+        )
+        .with_filter(Filter::default().with(Rule::Languages(vec![String::from("en")])));
+
+        assert!(uper.plan(Update::OnlyFromLocal).is_empty());
+        assert!(uper.plan(Update::OnlyFromLocalMirror).is_empty());
+    }
+
     #[test]
     fn copy_files_check() {
         let uper = Updater::new(
@@ -247,7 +1232,7 @@ mod tests {
             PathBuf::from("tests/copy_files/foreign"),
         );
 
-        let (from_local, _) = cross_diff(uper.scan_area());
+        let (from_local, _) = uper.with_sides(cross_diff);
 
         let ixer_res: Vec<_> = from_local.iter().cloned().collect();
         assert_eq!(ixer_res, []);
@@ -260,12 +1245,11 @@ mod tests {
             fs::remove_dir("tests/copy_files/foreign/test")?;
             Ok(())
         })();
-        match delete {
-            Err(e) => panic!("Delete error: {}", e),
-            Ok(_) => (),
+        if let Err(e) = delete {
+            panic!("Delete error: {}", e);
         }
 
-        let (from_local, from_foreign) = cross_diff(uper.scan_area());
+        let (from_local, from_foreign) = uper.with_sides(cross_diff);
 
         let ixer_res: Vec<_> = from_local.iter().cloned().collect();
         assert_eq!(
@@ -278,7 +1262,7 @@ mod tests {
             ]
         );
 
-        let results_of_copy = copy_files(from_local, from_foreign.get_path())
+        let results_of_copy = execute(plan_copy(&from_local, from_foreign.get_path()), None, 1)
             .iter()
             .map(|e| (e.get_name().to_string(), e.get_status().clone()))
             .collect::<Vec<(String, BookTransferStatus)>>();
@@ -292,7 +1276,7 @@ mod tests {
             ]
         );
 
-        let (from_local, _) = cross_diff(uper.scan_area());
+        let (from_local, _) = uper.with_sides(cross_diff);
 
         let ixer_res: Vec<_> = from_local.iter().cloned().collect();
         assert_eq!(ixer_res, []);
@@ -313,20 +1297,589 @@ mod tests {
             fs::remove_dir("tests/move_files/foreign/test")?;
             Ok(())
         })();
-        match delete {
-            Err(e) => panic!("Delete error: {}", e),
-            Ok(_) => (),
+        if let Err(e) = delete {
+            panic!("Delete error: {}", e);
         }
 
         let (from_local, from_foreign) = uper.scan_area();
 
-        let results_of_move = move_files(from_local, from_foreign)
+        let hashes = Mutex::new(BTreeMap::new());
+        let results_of_move = execute(
+            plan_move(&from_local, &from_foreign, Links::Ignore, &hashes),
+            None,
+            1,
+        )
+        .iter()
+        .map(|e| (e.get_name().to_string(), e.get_status().clone()))
+        .collect::<Vec<(String, BookTransferStatus)>>();
+        assert_eq!(
+            results_of_move,
+            [(String::from("file_four.txt"), BookTransferStatus::Moved),]
+        );
+    }
+
+    #[test]
+    fn rename_files_check() {
+        let uper = Updater::new(
+            PathBuf::from("tests/rename_files/local"),
+            PathBuf::from("tests/rename_files/foreign"),
+        );
+
+        let rename: std::io::Result<()> = (|| {
+            fs::rename(
+                "tests/rename_files/foreign/test/file_five.txt",
+                "tests/rename_files/foreign/file_six.txt",
+            )?;
+            fs::remove_dir("tests/rename_files/foreign/test")?;
+            Ok(())
+        })();
+        if let Err(e) = rename {
+            panic!("Rename error: {}", e);
+        }
+
+        let results_of_move = uper
+            .update(Update::OnlyFromLocalSync)
             .iter()
             .map(|e| (e.get_name().to_string(), e.get_status().clone()))
             .collect::<Vec<(String, BookTransferStatus)>>();
         assert_eq!(
             results_of_move,
-            [(String::from("file_four.txt"), BookTransferStatus::Moved),]
+            [(String::from("file_five.txt"), BookTransferStatus::Renamed),]
+        );
+
+        assert!(PathBuf::from("tests/rename_files/foreign/test/file_five.txt").exists());
+        assert!(!PathBuf::from("tests/rename_files/foreign/file_six.txt").exists());
+    }
+
+    #[test]
+    fn delete_files_check() {
+        let uper = Updater::new(
+            PathBuf::from("tests/delete_files/local"),
+            PathBuf::from("tests/delete_files/foreign"),
+        );
+
+        assert_eq!(uper.update(Update::Deletion), []);
+        assert_eq!(uper.store_state(), Ok(()));
+
+        if let Err(e) = fs::remove_file("tests/delete_files/foreign/file_two.txt") {
+            panic!("Delete error: {}", e);
+        }
+
+        let results_of_trash = uper
+            .update(Update::Deletion)
+            .iter()
+            .map(|e| (e.get_name().to_string(), e.get_status().clone()))
+            .collect::<Vec<(String, BookTransferStatus)>>();
+        assert_eq!(
+            results_of_trash,
+            [(String::from("file_two.txt"), BookTransferStatus::Trashed),]
+        );
+        assert!(!PathBuf::from("tests/delete_files/local/file_two.txt").exists());
+
+        let trash = Trash::new(PathBuf::from("tests/delete_files/local"));
+        let batches = trash.batches().unwrap();
+        assert_eq!(batches.len(), 1);
+
+        let results_of_restore = trash
+            .restore(&batches[0])
+            .iter()
+            .map(|e| (e.get_name().to_string(), e.get_status().clone()))
+            .collect::<Vec<(String, BookTransferStatus)>>();
+        assert_eq!(
+            results_of_restore,
+            [(String::from("file_two.txt"), BookTransferStatus::Restored),]
+        );
+        assert!(PathBuf::from("tests/delete_files/local/file_two.txt").exists());
+        assert!(!trash.get_path().exists());
+
+        let restore: std::io::Result<()> = (|| {
+            fs::copy(
+                "tests/delete_files/local/file_two.txt",
+                "tests/delete_files/foreign/file_two.txt",
+            )?;
+            fs::remove_file(uper.state_path())?;
+            Ok(())
+        })();
+        if let Err(e) = restore {
+            panic!("Restore error: {}", e);
+        }
+    }
+
+    #[test]
+    fn directions_plan_check() {
+        let directions = Directions::new(Direction::Pull)
+            .with_rule(
+                patterns(&[String::from("Fiction")]).unwrap().remove(0),
+                Direction::Mirror,
+            )
+            .with_rule(
+                patterns(&[String::from("Private")]).unwrap().remove(0),
+                Direction::Ignore,
+            );
+
+        let uper = Updater::new(
+            PathBuf::from("tests/directions/local"),
+            PathBuf::from("tests/directions/foreign"),
+        )
+        .with_directions(directions);
+
+        let plan = |update| {
+            uper.plan(update)
+                .iter()
+                .map(|e| (e.get_name().to_string(), e.get_status().clone()))
+                .collect::<Vec<(String, BookTransferStatus)>>()
+        };
+
+        assert_eq!(
+            plan(Update::OnlyFromLocal),
+            [(
+                String::from("fiction_local.txt"),
+                BookTransferStatus::Copied
+            )]
+        );
+        assert_eq!(
+            plan(Update::OnlyFromLocalMirror),
+            [(String::from("extra.txt"), BookTransferStatus::Trashed)]
+        );
+        assert_eq!(
+            plan(Update::OnlyFromForeign),
+            [
+                (
+                    String::from("science_foreign.txt"),
+                    BookTransferStatus::Copied
+                ),
+                (String::from("store_book.txt"), BookTransferStatus::Copied),
+            ]
+        );
+        assert_eq!(plan(Update::OnlyFromLocalSync), []);
+    }
+
+    #[test]
+    fn inbox_check() {
+        let uper = Updater::new(
+            PathBuf::from("tests/inbox/local"),
+            PathBuf::from("tests/inbox/foreign"),
+        )
+        .with_inbox(Inbox::new(PathBuf::from("Inbox"), Filing::Series));
+
+        let results_of_copy = uper
+            .update(Update::OnlyFromForeign)
+            .iter()
+            .map(|e| (e.get_dst().clone(), e.get_status().clone()))
+            .collect::<Vec<(PathBuf, BookTransferStatus)>>();
+        assert_eq!(
+            results_of_copy,
+            [(
+                PathBuf::from(
+                    "tests/inbox/local/Inbox/John Ronald Reuel Tolkien/Middle-earth/hobbit.fb2"
+                ),
+                BookTransferStatus::Copied
+            )]
+        );
+        assert_eq!(uper.store_state(), Ok(()));
+
+        // Remembered inbox books are neither moved nor copied again
+        let uper = Updater::new(
+            PathBuf::from("tests/inbox/local"),
+            PathBuf::from("tests/inbox/foreign"),
+        );
+        assert_eq!(uper.plan(Update::OnlyFromForeignSync), []);
+        assert_eq!(uper.plan(Update::OnlyFromLocalSync), []);
+        assert_eq!(uper.plan(Update::OnlyFromForeign), []);
+
+        let restore: std::io::Result<()> = (|| {
+            fs::remove_dir_all("tests/inbox/local/Inbox")?;
+            fs::remove_file(uper.state_path())?;
+            Ok(())
+        })();
+        if let Err(e) = restore {
+            panic!("Restore error: {}", e);
+        }
+    }
+
+    struct LastProgress(Arc<Mutex<Progress>>);
+
+    impl Observer for LastProgress {
+        fn finished(&self, progress: &Progress) {
+            *self.0.lock().unwrap() = progress.clone();
+        }
+    }
+
+    #[test]
+    fn observed_copy_check() {
+        let foreign = PathBuf::from("tests/observed/foreign");
+        let last = Arc::new(Mutex::new(Progress::default()));
+        let uper = Updater::new(PathBuf::from("tests/iterate"), foreign.clone())
+            .with_observer(Box::new(LastProgress(last.clone())));
+
+        let results = uper.update(Update::OnlyFromLocal);
+        let copied = foreign.join("file_one.txt").exists();
+
+        if let Err(e) = fs::remove_dir_all("tests/observed") {
+            panic!("Delete error: {}", e);
+        }
+
+        let bytes = results.iter().filter_map(|r| r.get_bytes()).sum::<u64>();
+        let last = last.lock().unwrap();
+        assert!(copied);
+        assert_eq!(results.len(), 3);
+        assert_eq!(last.get_files_done(), 3);
+        assert_eq!(last.get_bytes_done(), bytes);
+        assert_eq!(last.bytes_remaining(), 0);
+    }
+
+    #[test]
+    fn parallel_copy_check() {
+        let foreign = PathBuf::from("tests/parallel/foreign");
+        let uper =
+            Updater::new(PathBuf::from("tests/iterate"), foreign.clone()).with_concurrency(3);
+
+        let plan = uper.plan(Update::OnlyFromLocal);
+        let results = uper.update(Update::OnlyFromLocal);
+        let copied = fs::read_dir(&foreign).map(|d| d.count()).unwrap_or(0);
+
+        if let Err(e) = fs::remove_dir_all("tests/parallel") {
+            panic!("Delete error: {}", e);
+        }
+
+        assert_eq!(copied, 3);
+        assert_eq!(results, plan);
+    }
+
+    #[test]
+    fn parallel_moves_check() {
+        let dir = PathBuf::from("tests/parallel_moves");
+        if let Err(e) = fs::create_dir(&dir) {
+            panic!("Create error: {}", e);
+        }
+        for name in ["file_0.txt", "book.txt"].iter() {
+            if let Err(e) = fs::write(dir.join(name), name) {
+                panic!("Write error: {}", e);
+            }
+        }
+
+        // Every move takes book left by previous one
+        let mut plan = (0..8)
+            .map(|i| {
+                BookStatus::new(
+                    &Book::from(dir.join(format!("file_{}.txt", i))),
+                    dir.join(format!("file_{}.txt", i + 1)),
+                    BookTransferStatus::Moved,
+                )
+            })
+            .collect::<Vec<BookStatus>>();
+        plan.push(BookStatus::new(
+            &Book::from(dir.join("book.txt")),
+            dir.join("copy/book.txt"),
+            BookTransferStatus::Copied,
+        ));
+
+        let results = transfer_parallel(plan, None, 4);
+        let moved = dir.join("file_8.txt").exists();
+
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            panic!("Delete error: {}", e);
+        }
+
+        assert!(moved);
+        assert!(results
+            .iter()
+            .all(|r| !matches!(r.get_status(), BookTransferStatus::Error(_))));
+        assert_eq!(results[8].get_dst(), &dir.join("copy/book.txt"));
+    }
+
+    #[test]
+    fn transfer_error_kind() {
+        let book = Book::from(PathBuf::from("tests/iterate/file_four.txt"));
+        let result = transfer(
+            BookStatus::new(
+                &book,
+                PathBuf::from("tests/iterate/file_five.txt"),
+                BookTransferStatus::Copied,
+            ),
+            None,
+        );
+
+        assert!(matches!(result.get_status(), BookTransferStatus::Error(_)));
+        assert_eq!(result.get_error_kind(), Some(&String::from("not_found")));
+        assert_eq!(result.get_bytes(), None);
+    }
+
+    #[test]
+    fn template_check() {
+        let local = PathBuf::from("tests/template/local");
+        let foreign = PathBuf::from("tests/template/foreign");
+        if let Err(e) = fs::create_dir(&foreign) {
+            panic!("Create error: {}", e);
+        }
+
+        let template =
+            Template::parse("{authors[0].last}/{series}/{series_index:02} - {title}.{ext}")
+                .unwrap();
+        let uper = Updater::new(local.clone(), foreign.clone()).with_template(template);
+
+        let results_of_copy = uper
+            .update(Update::OnlyFromLocal)
+            .iter()
+            .map(|e| (e.get_dst().clone(), e.get_status().clone()))
+            .collect::<Vec<(PathBuf, BookTransferStatus)>>();
+        assert_eq!(
+            results_of_copy,
+            [(
+                foreign.join("Tolkien/Middle-earth/01 - The Hobbit.fb2"),
+                BookTransferStatus::Copied
+            )]
+        );
+        assert_eq!(uper.store_state(), Ok(()));
+
+        // Placed copy is recognized as local book by later runs
+        let uper = Updater::new(local, foreign.clone());
+        assert_eq!(uper.plan(Update::OnlyFromLocal), []);
+        assert_eq!(uper.plan(Update::OnlyFromForeign), []);
+        assert_eq!(uper.plan(Update::OnlyFromLocalSync), []);
+        assert_eq!(uper.plan(Update::OnlyFromForeignSync), []);
+        assert_eq!(
+            uper.compare(false)
+                .iter()
+                .map(|c| c.get_difference().clone())
+                .collect::<Vec<Difference>>(),
+            [Difference::Same]
+        );
+
+        // Placed copy is selected by path of its local book
+        let uper = Updater::new(PathBuf::from("tests/template/local"), foreign.clone())
+            .with_filter(Filter::default().with(Rule::Include(vec![PathBuf::from("Fiction")])));
+        assert_eq!(uper.plan(Update::OnlyFromLocal), []);
+        assert_eq!(uper.plan(Update::OnlyFromLocalMirror), []);
+
+        if let Err(e) = fs::remove_dir_all(&foreign) {
+            panic!("Restore error: {}", e);
+        }
+    }
+
+    #[test]
+    fn template_collision_check() {
+        let mut books = Bookshelf::from(PathBuf::from("tests/template/local"));
+        books.add(Book::new(String::from("file_one.txt")));
+        books.add(Book::new(String::from("file_two.txt")));
+        let foreign = PathBuf::from("tests/template");
+
+        // Second book rendered to the same path isn't copied over first one
+        let plan = plan_template(&books, &foreign, &Template::parse("book.{ext}").unwrap());
+        assert_eq!(plan[0].get_status(), &BookTransferStatus::Copied);
+        assert!(matches!(plan[1].get_status(), BookTransferStatus::Error(_)));
+
+        // Existing file isn't overwritten
+        let plan = plan_template(
+            &books,
+            &foreign,
+            &Template::parse("local/Fiction/hobbit.fb2").unwrap(),
+        );
+        assert!(plan
+            .iter()
+            .all(|b| matches!(b.get_status(), BookTransferStatus::Error(_))));
+    }
+
+    #[test]
+    fn inbox_collision_check() {
+        let mut books = Bookshelf::from(PathBuf::from("tests/inbox/foreign"));
+        books.add(Book::new(String::from("hobbit.fb2")));
+        books.add(Book::new(String::from("own.txt")));
+
+        // Existing file of local side isn't overwritten by filed book
+        let plan = plan_inbox(
+            &books,
+            &PathBuf::from("tests/inbox/local"),
+            &Inbox::new(PathBuf::from("Fiction"), Filing::Flat),
+        );
+        assert_eq!(plan[0].get_status(), &BookTransferStatus::Copied);
+        assert!(matches!(plan[1].get_status(), BookTransferStatus::Error(_)));
+    }
+
+    /// Sides with own book, linked book and linked folder of other folder
+    #[cfg(unix)]
+    fn linked_side(root: &Path, sides: &[&str]) -> std::io::Result<()> {
+        let outside = root.join("outside");
+        fs::create_dir_all(outside.join("dir"))?;
+        fs::create_dir_all(root.join("local"))?;
+        fs::create_dir_all(root.join("foreign"))?;
+        fs::write(outside.join("linked.txt"), "linked book")?;
+        fs::write(outside.join("dir/inner.txt"), "inner book")?;
+        fs::write(root.join("local/book.txt"), "own book")?;
+        for side in sides.iter() {
+            let side = root.join(side);
+            std::os::unix::fs::symlink("../outside/linked.txt", side.join("linked.txt"))?;
+            std::os::unix::fs::symlink("../outside/dir", side.join("dir"))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_policy_check() {
+        let root = PathBuf::from("tests/links_policy");
+        let created = linked_side(&root, &["local"]);
+        let (local, foreign) = (root.join("local"), root.join("foreign"));
+        let names = |plan: Vec<BookStatus>| {
+            plan.iter()
+                .map(|b| b.get_name().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        let plans = [Links::Ignore, Links::Follow, Links::Copy]
+            .iter()
+            .map(|links| {
+                let uper =
+                    Updater::new(local.clone(), foreign.clone()).with_links(*links, Links::Ignore);
+                names(uper.plan(Update::OnlyFromLocal))
+            })
+            .collect::<Vec<Vec<String>>>();
+
+        // Copy policy copies target of linked book as regular file
+        let copied = Updater::new(local.clone(), foreign.clone())
+            .with_links(Links::Copy, Links::Ignore)
+            .update(Update::OnlyFromLocal);
+        let regular = fs::symlink_metadata(foreign.join("linked.txt"))
+            .map(|m| m.file_type().is_file())
+            .unwrap_or(false);
+
+        if let Err(e) = fs::remove_dir_all(&root) {
+            panic!("Delete error: {}", e);
+        }
+
+        assert!(created.is_ok());
+        assert_eq!(plans[0], ["book.txt"]);
+        assert_eq!(plans[1], ["book.txt", "inner.txt", "linked.txt"]);
+        assert_eq!(plans[2], plans[1]);
+        assert_eq!(copied.len(), 3);
+        assert!(regular);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn linked_outside_root_check() {
+        let root = PathBuf::from("tests/linked_outside");
+        let created = linked_side(&root, &["foreign"]).and_then(|_| {
+            fs::create_dir(root.join("local/Fiction"))?;
+            fs::write(root.join("local/Fiction/inner.txt"), "inner book")
+        });
+
+        // Books of linked folder are neither moved nor trashed, link to book
+        // is trashed itself
+        let uper = Updater::new(root.join("local"), root.join("foreign"))
+            .with_links(Links::Ignore, Links::Follow);
+        let moved = uper.update(Update::OnlyFromLocalSync);
+        let deleted = fs::remove_dir_all(root.join("local/Fiction"));
+        // Linked books of copy policy aren't trashed at all
+        let copy_plan = Updater::new(root.join("local"), root.join("foreign"))
+            .with_links(Links::Ignore, Links::Copy)
+            .plan(Update::OnlyFromLocalMirror)
+            .iter()
+            .map(|b| b.get_error_kind().cloned())
+            .collect::<Vec<Option<String>>>();
+        let trashed = uper
+            .update(Update::OnlyFromLocalMirror)
+            .iter()
+            .map(|b| (b.get_name().to_string(), b.get_error_kind().cloned()))
+            .collect::<Vec<(String, Option<String>)>>();
+        let kept = ["outside/dir/inner.txt", "outside/linked.txt"]
+            .iter()
+            .all(|path| root.join(path).exists());
+
+        if let Err(e) = fs::remove_dir_all(&root) {
+            panic!("Delete error: {}", e);
+        }
+
+        assert!(created.is_ok() && deleted.is_ok());
+        assert_eq!(moved.len(), 1);
+        assert_eq!(
+            moved[0].get_error_kind(),
+            Some(&String::from("invalid_input"))
+        );
+        assert_eq!(
+            trashed,
+            [
+                (
+                    String::from("inner.txt"),
+                    Some(String::from("invalid_input"))
+                ),
+                (String::from("linked.txt"), None)
+            ]
+        );
+        assert!(kept);
+        assert_eq!(copy_plan, vec![Some(String::from("invalid_input")); 2]);
+    }
+
+    #[test]
+    fn local_index_reuse_check() {
+        let local = PathBuf::from("tests/fan_out/local");
+        let destination = PathBuf::from("tests/fan_out/first");
+        let index = Indexer::new(local.clone()).index();
+
+        // Source changed after index is built, given index is used anyway
+        let create: std::io::Result<()> = (|| {
+            fs::write(local.join("file_four.txt"), "fourth book")?;
+            fs::create_dir(&destination)?;
+            fs::write(destination.join("file_three.txt"), "third book")?;
+            Ok(())
+        })();
+        if let Err(e) = create {
+            panic!("Create error: {}", e);
+        }
+
+        let (_, local_report) = Indexer::new(PathBuf::from("tests/missing")).scan();
+        let uper = Updater::new(local.clone(), destination.clone())
+            .with_local_index(index)
+            .with_local_report(local_report.clone());
+
+        let plan = uper
+            .plan(Update::OnlyFromLocal)
+            .iter()
+            .map(|e| e.get_name().to_string())
+            .collect::<Vec<String>>();
+        let results = uper
+            .update(Update::OnlyFromForeign)
+            .iter()
+            .map(|e| (e.get_name().to_string(), e.get_status().clone()))
+            .collect::<Vec<(String, BookTransferStatus)>>();
+
+        let reports = (uper.index_report(), uper.foreign_report());
+
+        // Copies to source are tracked by given index for next destination
+        let index = uper
+            .into_local_index()
+            .unwrap()
+            .iter()
+            .map(|b| b.get_name().to_string())
+            .collect::<Vec<String>>();
+
+        let delete: std::io::Result<()> = (|| {
+            fs::remove_dir_all(&destination)?;
+            fs::remove_file(local.join("file_three.txt"))?;
+            fs::remove_file(local.join("file_four.txt"))?;
+            Ok(())
+        })();
+        if let Err(e) = delete {
+            panic!("Delete error: {}", e);
+        }
+
+        // Errors of given index are left to its owner
+        assert_eq!(reports, (local_report, IndexReport::new()));
+        assert_eq!(
+            plan,
+            [String::from("file_one.txt"), String::from("file_two.txt")]
+        );
+        assert_eq!(
+            results,
+            [(String::from("file_three.txt"), BookTransferStatus::Copied)]
+        );
+        assert_eq!(
+            index,
+            [
+                String::from("file_one.txt"),
+                String::from("file_three.txt"),
+                String::from("file_two.txt"),
+            ]
         );
     }
 
@@ -337,7 +1890,7 @@ mod tests {
             PathBuf::from("tests/update_files/foreign"),
         );
 
-        let (from_local, from_foreign) = cross_diff(uper.scan_area());
+        let (from_local, from_foreign) = uper.with_sides(cross_diff);
 
         let ixer_res: Vec<_> = from_local.iter().cloned().collect();
         assert_eq!(ixer_res, []);
@@ -358,12 +1911,11 @@ mod tests {
             Ok(())
         };
 
-        match delete() {
-            Err(e) => panic!("Delete error: {}", e),
-            Ok(_) => (),
+        if let Err(e) = delete() {
+            panic!("Delete error: {}", e);
         }
 
-        let (from_local, from_foreign) = cross_diff(uper.scan_area());
+        let (from_local, from_foreign) = uper.with_sides(cross_diff);
 
         let ixer_res: Vec<_> = from_local.iter().cloned().collect();
         assert_eq!(ixer_res, [Book::new(String::from("file_three.txt")),]);
@@ -384,7 +1936,7 @@ mod tests {
             ]
         );
 
-        let (from_local, from_foreign) = cross_diff(uper.scan_area());
+        let (from_local, from_foreign) = uper.with_sides(cross_diff);
 
         let ixer_res: Vec<_> = from_local.iter().cloned().collect();
         assert_eq!(ixer_res, []);
@@ -392,9 +1944,8 @@ mod tests {
         let ixer_res: Vec<_> = from_foreign.iter().cloned().collect();
         assert_eq!(ixer_res, []);
 
-        match delete() {
-            Err(e) => panic!("Delete error: {}", e),
-            Ok(_) => (),
+        if let Err(e) = delete() {
+            panic!("Delete error: {}", e);
         }
 
         let mut results_of_copy_two = uper.update(Update::OnlyFromLocal);
@@ -2,19 +2,80 @@
 //!
 //! Service and utitility units
 
-use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
 
-pub fn create_dir_for_path(path: &PathBuf) -> io::Result<()> {
+pub fn create_dir_for_path(path: &Path) -> io::Result<()> {
     let path_dir = path.parent().unwrap();
 
     if !path_dir.exists() {
-        match create_dir_for_path(&path_dir.to_path_buf()) {
-            Ok(_) => fs::create_dir(path_dir),
-            Err(x) => Err(x),
+        match create_dir_for_path(path_dir).and_then(|_| fs::create_dir(path_dir)) {
+            // NOTE: dir can be created by parallel transfer meanwhile
+            Err(x) if x.kind() == io::ErrorKind::AlreadyExists && path_dir.is_dir() => Ok(()),
+            result => result,
         }
     } else {
         Ok(())
     }
 }
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Content hash of file (FNV-1a 64), stable between runs and platforms
+pub fn file_hash(path: &Path) -> io::Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer = [0u8; 8192];
+    let mut hash = FNV_OFFSET_BASIS;
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        for byte in &buffer[..read] {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Hash of text (FNV-1a 64), stable between runs and platforms
+pub fn text_hash(text: &str) -> u64 {
+    text.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Snake case name of io error kind, like `not_found`
+pub fn error_kind_name(kind: io::ErrorKind) -> String {
+    let mut name = String::new();
+
+    for (i, c) in format!("{:?}", kind).chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+
+    name
+}
+
+/// File name part from free text, path separators are replaced
+pub fn path_safe(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c => c,
+        })
+        .collect()
+}